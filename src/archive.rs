@@ -0,0 +1,412 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+
+use crate::matcher::Matcher;
+use crate::stats::Stats;
+use crate::{decompress, inflate, read_memory, search_member, warn, MinigrepOptions};
+
+enum Kind {
+    Tar,
+    Zip,
+}
+
+const TAR_EXTENSIONS: &[&str] = &[".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz", ".tar.zst"];
+const ZIP_EXTENSIONS: &[&str] = &[".zip", ".jar"];
+
+fn kind_of(filename: &str) -> Option<Kind> {
+    let lowercase = filename.to_ascii_lowercase();
+    if TAR_EXTENSIONS.iter().any(|extension| lowercase.ends_with(extension)) {
+        Some(Kind::Tar)
+    } else if ZIP_EXTENSIONS.iter().any(|extension| lowercase.ends_with(extension)) {
+        Some(Kind::Zip)
+    } else {
+        None
+    }
+}
+
+// Searches every regular file in the archive as if it were a file of its own
+// named archive!member. Returns None if `filename` isn't an archive.
+pub fn search_archive(options: &MinigrepOptions, matcher: &Matcher, filename: &str) -> Option<Stats> {
+    let kind = kind_of(filename)?;
+    let mut total = Stats::default();
+    let result = match kind {
+        Kind::Tar => search_tar(options, matcher, filename, &mut total),
+        Kind::Zip => search_zip(options, matcher, filename, &mut total),
+    };
+    if let Err(e) = result {
+        warn(options, &format!("error reading archive {}: {}", filename, e));
+    }
+    Some(total)
+}
+
+// Reads until `buf` is full or the input ends, returning how much was read.
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+// Sizes are octal, or for files of 8GiB and more big-endian binary marked by
+// the high bit of the first byte.
+fn tar_size(field: &[u8]) -> Result<u64, String> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold(0, |size, &b| (size << 8) | b as u64));
+    }
+    let digits = tar_string(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| format!("invalid size in tar header: {:?}", digits))
+}
+
+// Finds the path in a pax extended header, made of "LENGTH key=value\n"
+// records.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .filter_map(|record| record.split_once(' ').map(|(_, field)| field))
+        .find_map(|field| field.strip_prefix("path="))
+        .map(str::to_string)
+}
+
+fn search_tar(options: &MinigrepOptions, matcher: &Matcher, filename: &str, total: &mut Stats) -> Result<(), String> {
+    let mut reader: Box<dyn BufRead> = if filename.to_ascii_lowercase().ends_with(".tar") {
+        Box::new(BufReader::new(File::open(filename).map_err(|e| e.to_string())?))
+    } else {
+        decompress::open(filename)
+            .map_err(|e| e.to_string())?
+            .ok_or("unknown compression")?
+    };
+    // GNU tar and pax store long names in an entry before the one they name.
+    let mut long_name = None;
+    loop {
+        let mut header = [0u8; 512];
+        let read = read_full(&mut reader, &mut header).map_err(|e| e.to_string())?;
+        // An archive ends with two zero blocks, but a missing end is tolerated.
+        if read < header.len() || header.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+        let size = tar_size(&header[124..136])?;
+        let padding = (512 - size % 512) % 512;
+        let kind = header[156];
+        let mut data = (&mut reader).take(size);
+        match kind {
+            b'L' | b'x' => {
+                let mut value = Vec::new();
+                data.read_to_end(&mut value).map_err(|e| e.to_string())?;
+                long_name = if kind == b'L' { Some(tar_string(&value)) } else { pax_path(&value) };
+            }
+            b'0' | b'7' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let name = tar_string(&header[0..100]);
+                    let prefix = tar_string(&header[345..500]);
+                    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                        format!("{}/{}", prefix, name)
+                    } else {
+                        name
+                    }
+                });
+                let display = format!("{}!{}", filename, name);
+                if search_member(options, matcher, &display, Box::new(&mut data), total) {
+                    return Ok(());
+                }
+            }
+            _ => long_name = None,
+        }
+        io::copy(&mut data, &mut io::sink()).map_err(|e| e.to_string())?;
+        io::copy(&mut (&mut reader).take(padding), &mut io::sink()).map_err(|e| e.to_string())?;
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as u64
+}
+
+// The end of central directory record is at most 22 bytes plus a 64KiB
+// comment from the end of the file.
+const END_RECORD_SEARCH: u64 = 22 + 0xffff;
+
+fn search_zip(options: &MinigrepOptions, matcher: &Matcher, filename: &str, total: &mut Stats) -> Result<(), String> {
+    read_zip(filename, &|_| true, read_memory(options), &mut |name, contents| {
+        let display = format!("{}!{}", filename, name);
+        match contents {
+            Ok(contents) => !search_member(options, matcher, &display, Box::new(Cursor::new(contents)), total),
+            Err(e) => {
+                warn(options, &format!("skipping {}: {}", display, e));
+                true
+            }
+        }
+    })
+}
+
+// The contents of a zip member, or why they couldn't be read.
+pub type ZipMember = Result<Vec<u8>, String>;
+
+// Reads the files in a zip archive whose names pass `wanted`, passing each
+// one's name and contents to `visit` until it returns false. A member that
+// can't be extracted, because it's encrypted, compressed in a way other than
+// deflate or bigger than `limit` bytes, is passed as an error.
+pub fn read_zip(
+    filename: &str,
+    wanted: &dyn Fn(&str) -> bool,
+    limit: Option<usize>,
+    visit: &mut dyn FnMut(&str, ZipMember) -> bool,
+) -> Result<(), String> {
+    let mut file = File::open(filename).map_err(|e| e.to_string())?;
+    let length = file.metadata().map_err(|e| e.to_string())?.len();
+    let tail_start = length.saturating_sub(END_RECORD_SEARCH);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(tail_start)).and_then(|_| file.read_to_end(&mut tail)).map_err(|e| e.to_string())?;
+    let end = tail.windows(4)
+        .rposition(|window| window == b"PK\x05\x06")
+        .filter(|&end| end + 22 <= tail.len())
+        .ok_or("not a zip file")?;
+    let directory_size = u32_at(&tail, end + 12);
+    let directory_offset = u32_at(&tail, end + 16);
+    if directory_offset == 0xffff_ffff {
+        return Err("zip64 archives aren't supported".to_string());
+    }
+    // Sizes are checked against the file's before anything that big is
+    // allocated, so a corrupt archive fails instead.
+    if directory_offset + directory_size > length {
+        return Err("the central directory runs past the end of the file".to_string());
+    }
+    let mut directory = vec![0; directory_size as usize];
+    file.seek(SeekFrom::Start(directory_offset))
+        .and_then(|_| file.read_exact(&mut directory))
+        .map_err(|e| e.to_string())?;
+
+    let mut at = 0;
+    while at + 46 <= directory.len() && &directory[at..at + 4] == b"PK\x01\x02" {
+        let flags = u16_at(&directory, at + 8);
+        let method = u16_at(&directory, at + 10);
+        let compressed_size = u32_at(&directory, at + 20);
+        let size = u32_at(&directory, at + 24);
+        let name_length = u16_at(&directory, at + 28);
+        let extra_length = u16_at(&directory, at + 30);
+        let comment_length = u16_at(&directory, at + 32);
+        let local_offset = u32_at(&directory, at + 42);
+        let name_end = (at + 46 + name_length).min(directory.len());
+        let name = String::from_utf8_lossy(&directory[at + 46..name_end]).into_owned();
+        at += 46 + name_length + extra_length + comment_length;
+
+        if name.ends_with('/') || !wanted(&name) {
+            continue;
+        }
+        if flags & 1 != 0 {
+            if !visit(&name, Err("encrypted archive members aren't supported".to_string())) {
+                return Ok(());
+            }
+            continue;
+        }
+        if limit.is_some_and(|limit| size.max(compressed_size) > limit as u64) {
+            if !visit(&name, Err("it's too big to be read whole within --max-memory".to_string())) {
+                return Ok(());
+            }
+            continue;
+        }
+        let mut local = [0u8; 30];
+        file.seek(SeekFrom::Start(local_offset))
+            .and_then(|_| file.read_exact(&mut local))
+            .map_err(|e| e.to_string())?;
+        if &local[..4] != b"PK\x03\x04" {
+            return Err(format!("bad local header for {}", name));
+        }
+        let skip = (u16_at(&local, 26) + u16_at(&local, 28)) as i64;
+        if local_offset + 30 + skip as u64 + compressed_size > length {
+            return Err(format!("{} runs past the end of the file", name));
+        }
+        let mut compressed = vec![0; compressed_size as usize];
+        file.seek(SeekFrom::Current(skip))
+            .and_then(|_| file.read_exact(&mut compressed))
+            .map_err(|e| e.to_string())?;
+        let contents = match method {
+            0 => Ok(compressed),
+            8 => inflate::inflate(&compressed, size as usize).map_err(|e| format!("couldn't decompress it: {}", e)),
+            _ => Err(format!("compression method {} isn't supported", method)),
+        };
+        if !visit(&name, contents) {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{pax_path, read_zip, search_archive, tar_size, ZipMember};
+    use crate::inflate::tests::unhex;
+    use crate::matcher::{Matcher, MatcherConfig};
+    use crate::{output, MinigrepOptions};
+
+    // A file in the temporary directory, removed when dropped.
+    pub(crate) struct TempFile(pub(crate) PathBuf);
+
+    impl TempFile {
+        pub(crate) fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!("minigrep-test-{}-{}", std::process::id(), name));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+
+        pub(crate) fn name(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn tar_entry(kind: u8, name: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[156] = kind;
+        let mut entry = header.to_vec();
+        entry.extend_from_slice(data);
+        entry.resize(entry.len() + (512 - data.len() % 512) % 512, 0);
+        entry
+    }
+
+    fn pax_record(key: &str, value: &str) -> String {
+        // The length counts itself, so it's found by trying.
+        let rest = format!(" {}={}\n", key, value);
+        let mut length = rest.len() + 1;
+        while format!("{}", length).len() + rest.len() != length {
+            length += 1;
+        }
+        format!("{}{}", length, rest)
+    }
+
+    // The names of the members of `archive` with a line matching `query`.
+    fn matching_members(archive: &TempFile, query: &str) -> String {
+        let options = MinigrepOptions {
+            files_with_matches: true,
+            threads: 1,
+            ..MinigrepOptions::default()
+        };
+        let matcher = Matcher::new(&[query.to_string()], &MatcherConfig::default()).unwrap();
+        let (_, captured) = output::capture(|| search_archive(&options, &matcher, archive.name()));
+        captured.into_string().replace(archive.name(), "archive")
+    }
+
+    #[test]
+    fn tar_sizes() {
+        assert_eq!(tar_size(b"00000001750\0"), Ok(1000));
+        assert_eq!(tar_size(b"     1750 \0\0"), Ok(1000));
+        assert_eq!(tar_size(b"\0\0\0\0\0\0\0\0\0\0\0\0"), Ok(0));
+        assert_eq!(tar_size(b"\x80\0\0\0\0\0\0\x02\0\0\0\0"), Ok(1 << 33));
+        assert!(tar_size(b"0000000875\0\0").is_err());
+    }
+
+    #[test]
+    fn pax_paths() {
+        let records = pax_record("mtime", "1.5") + &pax_record("path", "a dir/a file") + &pax_record("uid", "0");
+        assert_eq!(records.lines().next(), Some("13 mtime=1.5"));
+        assert_eq!(pax_path(records.as_bytes()).as_deref(), Some("a dir/a file"));
+        assert_eq!(pax_path(b"12 uid=1000\n"), None);
+    }
+
+    #[test]
+    fn tar_long_names() {
+        let gnu_name = format!("{}/gnu.txt", "d".repeat(120));
+        let pax_name = format!("{}/pax.txt", "p".repeat(120));
+        let mut tar = tar_entry(b'L', "././@LongLink", format!("{}\0", gnu_name).as_bytes());
+        tar.extend(tar_entry(b'0', "truncated gnu name", b"found\n"));
+        tar.extend(tar_entry(b'x', "PaxHeaders/pax", pax_record("path", &pax_name).as_bytes()));
+        tar.extend(tar_entry(b'0', "truncated pax name", b"found\n"));
+        tar.extend(tar_entry(b'0', "short.txt", b"found\n"));
+        tar.extend([0; 1024]);
+        let archive = TempFile::new("long-names.tar", &tar);
+        assert_eq!(
+            matching_members(&archive, "found"),
+            format!("archive!{}\narchive!{}\narchive!short.txt\n", gnu_name, pax_name),
+        );
+    }
+
+    // Made by Python's zipfile: a.txt stored, holding "stored text\n", and
+    // b.txt deflated, holding "deflated text deflated text\n".
+    const ZIP: &str = concat!(
+        "504b03041400000000000000210091eed3890c0000000c00000005000000612e74787473746f72656420746578740a504b03",
+        "04140000000800000021001a378953140000001c00000005000000622e7478744b494dcb492c494d512849ad28514841e671",
+        "0100504b010214031400000000000000210091eed3890c0000000c000000050000000000000000000000800100000000612e",
+        "747874504b01021403140000000800000021001a378953140000001c00000005000000000000000000000080012f00000062",
+        "2e747874504b0506000000000200020066000000660000000000",
+    );
+
+    fn zip_members(archive: &TempFile) -> Result<Vec<(String, ZipMember)>, String> {
+        let mut members = Vec::new();
+        read_zip(archive.name(), &|_| true, None, &mut |name, contents| {
+            members.push((name.to_string(), contents));
+            true
+        })?;
+        Ok(members)
+    }
+
+    #[test]
+    fn zip_members_are_read() {
+        let archive = TempFile::new("members.zip", &unhex(ZIP));
+        assert_eq!(
+            zip_members(&archive),
+            Ok(vec![
+                ("a.txt".to_string(), Ok(b"stored text\n".to_vec())),
+                ("b.txt".to_string(), Ok(b"deflated text deflated text\n".to_vec())),
+            ]),
+        );
+        assert_eq!(matching_members(&archive, "deflated"), "archive!b.txt\n");
+    }
+
+    #[test]
+    fn truncated_zip_is_an_error() {
+        let zip = unhex(ZIP);
+        for end in 0..zip.len() {
+            let archive = TempFile::new("truncated.zip", &zip[..end]);
+            assert!(zip_members(&archive).is_err(), "read {} bytes of {}", end, zip.len());
+        }
+    }
+
+    #[test]
+    fn corrupt_zip_doesnt_panic() {
+        let zip = unhex(ZIP);
+        let archive = TempFile::new("corrupt.zip", &zip);
+        for at in 0..zip.len() {
+            for bit in 0..8 {
+                let mut corrupt = zip.clone();
+                corrupt[at] ^= 1 << bit;
+                fs::write(&archive.0, &corrupt).unwrap();
+                let _ = zip_members(&archive);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_tar_doesnt_panic() {
+        let mut tar = tar_entry(b'L', "././@LongLink", b"long name\0");
+        tar.extend(tar_entry(b'0', "short", b"found\n"));
+        for end in 0..tar.len() {
+            let archive = TempFile::new("truncated.tar", &tar[..end]);
+            matching_members(&archive, "found");
+        }
+    }
+}
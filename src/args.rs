@@ -0,0 +1,746 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::color::ColorChoice;
+use crate::encoding::Encoding;
+use crate::format::OutputFormat;
+use crate::glob::Glob;
+use crate::hyperlink::HyperlinkFormat;
+use crate::ignore::IgnoreRules;
+use crate::matcher::EngineChoice;
+use crate::types::FileTypes;
+use crate::walk::SortKey;
+use crate::{read_memory, MinigrepOptions, MIN_READ_MEMORY};
+
+pub struct OptionSpec {
+    pub short: Option<char>,
+    pub long: &'static str,
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+const fn flag(short: Option<char>, long: &'static str, help: &'static str) -> OptionSpec {
+    OptionSpec { short, long, value: None, help }
+}
+
+const fn valued(short: Option<char>, long: &'static str, value: &'static str, help: &'static str) -> OptionSpec {
+    OptionSpec { short, long, value: Some(value), help }
+}
+
+// Every option the parser accepts; --help and shell completions are generated
+// from this table, so a new option only needs to be added here and in
+// `apply_option`.
+pub const OPTIONS: &[OptionSpec] = &[
+    valued(Some('e'), "regexp", "QUERY", "search for QUERY; may be repeated to match any of several queries"),
+    valued(Some('f'), "file", "FILE", "read queries from FILE, one per line; blank lines and # comments are ignored"),
+    flag(Some('F'), "fixed-strings", "treat the query as a literal string"),
+    flag(Some('i'), "ignore-case", "case-insensitive matching"),
+    flag(Some('S'), "smart-case", "case-insensitive unless the query contains uppercase"),
+    valued(None, "engine", "ENGINE", "regex engine: default, fancy for lookaround and backreferences (needs the \"fancy\" build feature) or auto (the default) to use fancy only for patterns that need it"),
+    valued(None, "encoding", "ENCODING", "decode input from ENCODING (utf-8, utf-16le, utf-16be, latin-1 or windows-1252; multi-byte encodings like shift_jis aren't supported) before matching; files that start with a UTF-8 or UTF-16 byte order mark are decoded by it instead"),
+    flag(None, "no-unicode", "make \\w, \\d, \\s, \\b and -i ASCII-only, which is faster on large ASCII inputs"),
+    valued(None, "regex-size-limit", "SIZE", "limit the size of each compiled regex, e.g. 100M; K, M and G suffixes are accepted"),
+    valued(None, "dfa-size-limit", "SIZE", "limit the memory each regex may use for its lazy DFA cache, e.g. 1G"),
+    flag(Some('w'), "word-regexp", "only match whole words"),
+    flag(Some('x'), "line-regexp", "only match whole lines"),
+    flag(Some('U'), "multiline", "let matches span lines; every line a match touches is printed"),
+    flag(None, "multiline-dotall", "with -U, let . match line endings too"),
+    flag(Some('v'), "invert-match", "print lines that do not match instead"),
+    flag(Some('g'), "dump-captures", "print the capture groups of each selected line, comma separated; named groups print as name=value"),
+    valued(None, "delimiter", "SEP", "separate -g capture groups with SEP instead of a comma; \\t, \\n and \\0 are unescaped"),
+    flag(None, "captures-json", "print the capture groups of each selected line as a JSON object keyed by group name, or _N for unnamed groups"),
+    valued(Some('R'), "replace", "TEMPLATE", "print selected lines with every match replaced by TEMPLATE, where $1 or $name refer to capture groups (use $$ for a literal $)"),
+    flag(None, "write", "rewrite each searched file in place with the --replace substitutions applied"),
+    flag(None, "interactive", "with --write, show each change and ask whether to apply it (y, n, a for all, q to quit)"),
+    flag(None, "diff", "print a unified diff of the changes --replace would make instead of writing any file"),
+    valued(None, "backup-suffix", "SUFFIX", "with --write, keep a copy of each changed file's original at its name plus SUFFIX, e.g. .bak"),
+    flag(Some('o'), "only-matching", "print only the matched parts of each line"),
+    flag(None, "overlapping", "also report matches that overlap earlier ones, e.g. \"aa\" three times in \"aaaa\""),
+    flag(None, "match-offsets", "print START-END:MATCH for every match, with byte offsets within the line; -b then gives each match's offset in the file"),
+    flag(None, "hex", "print each match with the bytes around it as a hex dump with file offsets, like xxd; input is read as raw bytes and binary files are searched too"),
+    flag(Some('n'), "line-number", "prefix each line with its line number"),
+    flag(None, "column", "prefix each line with the column of its first match (implies -n)"),
+    flag(Some('b'), "byte-offset", "prefix each line (or match with -o) with its byte offset"),
+    flag(Some('c'), "count", "print only a count of selected lines"),
+    flag(None, "count-matches", "print only a count of individual matches"),
+    valued(Some('m'), "max-count", "NUM", "stop reading a file after NUM selected lines"),
+    valued(None, "max-memory", "SIZE", "keep the memory minigrep's buffers use under about SIZE, e.g. 500M, by searching fewer files at once, limiting --max-line-size, skipping files -U or --encoding would read whole and zip archive members if they don't fit, and keeping output waiting to be printed in temporary files"),
+    valued(None, "max-line-size", "SIZE", "only search and print the first SIZE bytes of longer lines, with a note, so a huge line never has to fit in memory, e.g. 1M; files searched with -U or --encoding are still read whole"),
+    flag(Some('q'), "quiet", "print nothing and stop at the first match"),
+    valued(Some('A'), "after-context", "NUM", "print NUM lines of trailing context after each selected line"),
+    valued(Some('B'), "before-context", "NUM", "print NUM lines of leading context before each selected line"),
+    valued(Some('C'), "context", "NUM", "print NUM lines of context around each selected line"),
+    flag(None, "trim", "strip leading whitespace from printed lines"),
+    valued(Some('M'), "max-columns", "NUM", "don't print lines longer than NUM characters; a note with their number of matches is printed instead"),
+    flag(None, "max-columns-preview", "with --max-columns, print the first NUM characters of long lines instead of omitting them"),
+    flag(None, "passthru", "print every line, highlighting the selected ones; other lines print as context"),
+    valued(None, "context-separator", "SEP", "print SEP between non-contiguous context groups (default \"--\")"),
+    flag(None, "no-context-separator", "don't print a separator between context groups"),
+    valued(None, "color", "WHEN", "color file names, line numbers and matches: auto (default), always or never"),
+    flag(None, "no-color", "don't color anything, the same as --color never"),
+    valued(None, "colors", "SPEC", "override a color, e.g. match:fg:yellow, path:style:bold, line:none; may be repeated"),
+    valued(None, "hyperlink-format", "FORMAT", "link printed paths with OSC 8 hyperlinks when colors are on: a URL template using {path}, {line}, {column} and {host}, or one of default, vscode, cursor, kitty, macvim, textmate, grep+, none"),
+    flag(None, "vimgrep", "print file:line:column:text once for every match, for editor quickfix lists"),
+    valued(None, "format", "TEMPLATE", "print each selected line through a template using {path}, {line}, {column}, {text}, {match} and {N} for capture groups"),
+    flag(None, "json", "print results as a stream of JSON objects compatible with rg --json"),
+    flag(None, "heading", "print each file name once above its matches (default when output is a terminal)"),
+    flag(None, "no-heading", "print the file name on every line instead of as a heading"),
+    flag(None, "line-buffered", "write out each line as soon as it's printed (default when output is a terminal), e.g. when following a log with tail -f"),
+    flag(None, "block-buffered", "write output in large blocks, which is faster when printing many lines (default when output isn't a terminal)"),
+    flag(None, "stats", "after the results, print how many files were searched and matched, how many lines and matches were found, how many bytes were searched and how long it took (with --json, the summary already has them)"),
+    flag(None, "progress", "once a search has taken over a second, show how many files and bytes have been searched and how many lines matched so far, on standard error if it's a terminal"),
+    valued(None, "pre", "COMMAND", "search the output of COMMAND PATH for each file instead of the file itself, with the file also on its standard input; an empty COMMAND turns this off"),
+    valued(None, "pre-glob", "GLOB", "only run the --pre command on files matching GLOB, e.g. '*.pdf'; may be repeated"),
+    flag(None, "search-zip", "search inside gzip, bzip2, xz and zstd compressed files, found by extension or magic bytes; needs the matching command to be installed (it has no short form, as -Z is --null)"),
+    flag(None, "archives", "search each file inside tar (optionally compressed) and zip archives, printed as archive.zip!member/path"),
+    flag(Some('a'), "text", "search binary files as if they were text instead of only reporting that they match"),
+    flag(Some('s'), "no-messages", "suppress error messages about unreadable files"),
+    flag(Some('r'), "recursive", "search every file under directory arguments"),
+    valued(None, "include", "GLOB", "only search files whose names match GLOB, e.g. '*.rs'; may be repeated to allow several"),
+    valued(None, "exclude", "GLOB", "skip files whose names match GLOB, e.g. '*.min.js'; may be repeated"),
+    valued(Some('t'), "type", "TYPE", "only search files of TYPE, e.g. rust or py, as found by their names; may be repeated to allow several"),
+    valued(Some('T'), "type-not", "TYPE", "skip files of TYPE; may be repeated"),
+    valued(None, "type-add", "NAME:GLOBS", "define file type NAME for --type as the comma-separated GLOBS, e.g. 'web:*.html,*.css', or add them to an existing type; may be repeated and kept in the config file"),
+    flag(None, "type-list", "print every file type with its globs and exit"),
+    valued(None, "exclude-dir", "GLOB", "don't descend into directories whose names match GLOB, e.g. node_modules; may be repeated"),
+    valued(None, "max-depth", "NUM", "with -r, descend at most NUM directories below each directory argument; 1 searches only the files directly in it"),
+    valued(None, "max-filesize", "SIZE", "with -r, skip files bigger than SIZE, e.g. 50M; K, M and G suffixes are accepted"),
+    flag(None, "follow", "with -r, follow symbolic links to files and directories instead of skipping them; links back into a directory being searched are skipped"),
+    flag(None, "one-file-system", "with -r, don't descend into directories on other file systems than the directory argument, such as mount points"),
+    flag(None, "hidden", "with -r, also search hidden files and directories, whose names start with a dot"),
+    flag(None, "no-ignore", "with -r, also search files matched by .gitignore, .ignore and the global git excludes file"),
+    valued(None, "ignore-file", "FILE", "with -r, skip files matched by the gitignore-style rules in FILE, relative to the current directory; may be repeated"),
+    flag(None, "git-tracked", "with -r (implied), only search the files git tracks in directories that are inside a git repository"),
+    valued(Some('j'), "threads", "NUM", "search up to NUM files, or pieces of a large file, at once; 0, the default, uses one thread per CPU and 1 searches everything in turn"),
+    flag(None, "mmap", "read files through a memory map instead of buffered reads where possible, which is faster for large files but ends minigrep with SIGBUS if one shrinks during the search"),
+    flag(None, "no-mmap", "read files with buffered reads (the default)"),
+    flag(None, "no-sort", "when searching in parallel, print each file's results as soon as it's been searched instead of in the order the files were found"),
+    flag(None, "files", "print the files that would be searched, after every filter and ignore rule, without searching them; every argument is a path (implies -r)"),
+    flag(None, "filename-only", "match the query against the path of each file that would be searched instead of its contents, printing the paths that match (implies -r)"),
+    valued(None, "sort", "KEY", "search and print files in order of KEY: path, modified (oldest first), size (smallest first) or none; every file is found before any is searched"),
+    valued(None, "sortr", "KEY", "like --sort but in reverse, e.g. newest first with modified"),
+    valued(None, "rev", "REVISION", "search the files of a git commit, branch or tag instead of the work tree; file arguments are paths in it (default: the current directory)"),
+    valued(None, "files-from", "FILE", "also search the files named in FILE, one per line (\"-\" reads standard input)"),
+    flag(Some('0'), "files-from-null", "names in the --files-from list are separated by NUL instead of newline"),
+    flag(Some('H'), "with-filename", "prefix each line with its file name (default with more than one file)"),
+    flag(Some('h'), "no-filename", "never prefix lines with file names"),
+    flag(Some('z'), "null-data", "input and output records end in NUL instead of newline"),
+    flag(None, "crlf", "end printed lines with \\r\\n, as Windows text files do, instead of \\n"),
+    flag(Some('Z'), "null", "follow every printed file name with a NUL byte instead of ':' or a newline"),
+    flag(Some('l'), "files-with-matches", "print the name of each file that contains a match"),
+    flag(Some('L'), "files-without-match", "print the name of each file that contains no match"),
+    flag(None, "verbose", "print notes about how the search is run, such as which regex engine is used, to standard error"),
+    flag(None, "no-config", "don't read default options from the config file"),
+    flag(None, "help", "print this help and exit"),
+    flag(Some('V'), "version", "print the version and exit"),
+];
+
+pub enum Action {
+    Search(Box<MinigrepOptions>),
+    Help,
+    Version,
+    TypeList(String),
+    Completions(String),
+}
+
+pub fn print_usage(out: &mut dyn Write, prog_name: &str) {
+    let _ = writeln!(out, "usage: {} [options] query [file ...]", prog_name);
+    let _ = writeln!(out, "       {} [options] -e query [-e query ...] [file ...]", prog_name);
+    let _ = writeln!(out, "       {} [options] -f query_file [file ...]", prog_name);
+    let _ = writeln!(out, "       {} history [options] query [path ...]", prog_name);
+    let _ = writeln!(out, "       {} completions <{}>", prog_name, crate::completions::SHELLS.join("|"));
+    let _ = writeln!(out);
+    let _ = writeln!(out, "file: file path, a directory with -r, or an http(s) URL in builds with the \"net\" feature; \"-\" or no file reads standard input");
+    let _ = writeln!(out, "query: search string as regex");
+    let _ = writeln!(out, "options (\"--\" ends the options, so the query may begin with \"-\"):");
+    for spec in OPTIONS {
+        let mut names = match spec.short {
+            Some(short) => format!("-{}, --{}", short, spec.long),
+            None => format!("--{}", spec.long),
+        };
+        if let Some(value) = spec.value {
+            names.push(' ');
+            names.push_str(value);
+        }
+        let _ = writeln!(out, "    {}: {}", names, spec.help);
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(out, "Default options are read from $MINIGREP_CONFIG, or ~/.config/minigrep/config if it exists.");
+    let _ = writeln!(out, "Options in $MINIGREP_OPTS are applied after the config file, and options given");
+    let _ = writeln!(out, "on the command line override both. Any option without a value can be turned off");
+    let _ = writeln!(out, "again with --no-OPTION, e.g. --no-line-number to override -n given earlier.");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "Lines may end in \\n or \\r\\n. The line ending is not part of the line, so $ matches");
+    let _ = writeln!(out, "before a Windows line ending too and carriage returns are never printed. With --crlf,");
+    let _ = writeln!(out, "printed lines end in \\r\\n instead of \\n.");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "Exit status is 0 if any line was selected, 1 if none was, and 2 on error.");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "Author: Ethan Faust");
+    let _ = writeln!(out);
+}
+
+fn read_pattern_file(filename: &str) -> Result<Vec<String>, String> {
+    let file = File::open(filename)
+        .map_err(|e| format!("couldn't open {}: {}", filename, e))?;
+    let mut patterns = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("error reading {}: {}", filename, e))?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        patterns.push(line);
+    }
+    Ok(patterns)
+}
+
+fn parse_number(spec: &OptionSpec, value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("invalid value for --{}: {}", spec.long, value))
+}
+
+// Sizes are a number of bytes with an optional K, M or G suffix (powers of 1024).
+fn parse_size(spec: &OptionSpec, value: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid value for --{}: {}", spec.long, value);
+    let (digits, shift) = match value.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&value[..i], 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&value[..i], 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&value[..i], 30),
+        _ => (value, 0),
+    };
+    let number: usize = digits.parse().map_err(|_| invalid())?;
+    number.checked_mul(1 << shift).ok_or_else(invalid)
+}
+
+// Lets separators like tab or NUL be given without shell-specific quoting.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('0') => unescaped.push('\0'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// Settings whose final value depends on other options are collected here and
+// resolved once every argument has been seen.
+#[derive(Default)]
+struct PendingOptions {
+    after_context: Option<usize>,
+    before_context: Option<usize>,
+    context: Option<usize>,
+    with_filename: Option<bool>,
+    color: Option<ColorChoice>,
+    heading: Option<bool>,
+    line_buffered: Option<bool>,
+    types: Vec<String>,
+    types_not: Vec<String>,
+    file_types: FileTypes,
+    threads: Option<usize>,
+    type_list: bool,
+    patterns_given: bool,
+    help: bool,
+    version: bool,
+}
+
+// `on` is false for --no-FLAG, which turns a flag given earlier back off, e.g.
+// in the config file. Options a flag implies stay on, as they may have been
+// given themselves too.
+fn apply_option(
+    options: &mut MinigrepOptions,
+    pending: &mut PendingOptions,
+    spec: &OptionSpec,
+    value: Option<&str>,
+    on: bool,
+) -> Result<(), String> {
+    let value = value.unwrap_or_default();
+    match spec.long {
+        "regexp" => {
+            options.patterns.push(value.to_string());
+            pending.patterns_given = true;
+        }
+        "file" => {
+            options.patterns.extend(read_pattern_file(value)?);
+            pending.patterns_given = true;
+        }
+        "fixed-strings" => options.fixed_strings = on,
+        "ignore-case" => options.case_insensitive = on,
+        "smart-case" => options.smart_case = on,
+        "engine" => options.engine = EngineChoice::parse(value)?,
+        "encoding" => options.encoding = Some(Encoding::parse(value)?),
+        "no-unicode" => options.no_unicode = true,
+        "text" => options.text = on,
+        "search-zip" => options.search_zip = on,
+        "pre" => options.pre = Some(value.to_string()).filter(|command| !command.is_empty()),
+        "pre-glob" => options.pre_globs.push(Glob::new(value)?),
+        "archives" => options.archives = on,
+        "regex-size-limit" => options.regex_size_limit = Some(parse_size(spec, value)?),
+        "dfa-size-limit" => options.dfa_size_limit = Some(parse_size(spec, value)?),
+        "word-regexp" => options.word_regexp = on,
+        "line-regexp" => options.line_regexp = on,
+        "multiline" => options.multiline = on,
+        "multiline-dotall" => options.multiline_dotall = on,
+        "invert-match" => options.invert_match = on,
+        "dump-captures" => options.dump_capture_groups = on,
+        "delimiter" => options.capture_delimiter = unescape(value),
+        "captures-json" => options.captures_json = on,
+        "overlapping" => options.overlapping = on,
+        "match-offsets" => options.match_offsets = on,
+        "hex" => {
+            options.hex = on;
+            options.text |= on;
+        }
+        "replace" => options.replace = Some(value.to_string()),
+        "write" => options.write = on,
+        "interactive" => {
+            options.interactive = on;
+            options.write |= on;
+        }
+        "diff" => options.diff = on,
+        "backup-suffix" => options.backup_suffix = Some(value.to_string()),
+        "only-matching" => options.only_matching = on,
+        "line-number" => options.line_number = on,
+        "column" => {
+            options.column = on;
+            options.line_number |= on;
+        }
+        "byte-offset" => options.byte_offset = on,
+        "count" => options.count = on,
+        "count-matches" => options.count_matches = on,
+        "max-count" => options.max_count = Some(parse_number(spec, value)?),
+        "max-memory" => options.max_memory = Some(parse_size(spec, value)?),
+        "max-line-size" => options.max_line_size = Some(parse_size(spec, value)?),
+        "quiet" => options.quiet = on,
+        "after-context" => pending.after_context = Some(parse_number(spec, value)?),
+        "before-context" => pending.before_context = Some(parse_number(spec, value)?),
+        "context" => pending.context = Some(parse_number(spec, value)?),
+        "trim" => options.trim = on,
+        "max-columns" => options.max_columns = Some(parse_number(spec, value)?),
+        "max-columns-preview" => options.max_columns_preview = on,
+        "passthru" => options.passthru = on,
+        "context-separator" => options.context_separator = Some(value.to_string()),
+        "no-context-separator" => options.context_separator = None,
+        "color" => pending.color = Some(ColorChoice::parse(value)?),
+        "no-color" => pending.color = Some(ColorChoice::Never),
+        "colors" => options.palette.apply(value)?,
+        "hyperlink-format" => options.hyperlink = HyperlinkFormat::parse(value)?,
+        "vimgrep" => {
+            options.vimgrep = on;
+            options.line_number |= on;
+            options.column |= on;
+        }
+        "format" => options.format = Some(OutputFormat::parse(value)?),
+        "json" => options.json = on,
+        "heading" => pending.heading = Some(on),
+        "no-heading" => pending.heading = Some(false),
+        "stats" => options.stats = on,
+        "progress" => options.progress = on,
+        "line-buffered" => pending.line_buffered = Some(on),
+        "block-buffered" => pending.line_buffered = Some(!on),
+        "no-messages" => options.no_messages = true,
+        "recursive" => options.recursive = on,
+        "include" => options.includes.push(Glob::new(value)?),
+        "exclude" => options.excludes.push(Glob::new(value)?),
+        "type" => pending.types.push(value.to_string()),
+        "type-not" => pending.types_not.push(value.to_string()),
+        "type-add" => pending.file_types.add(value)?,
+        "type-list" => pending.type_list = on,
+        "exclude-dir" => options.exclude_dirs.push(Glob::new(value)?),
+        "max-depth" => options.max_depth = Some(parse_number(spec, value)?),
+        "max-filesize" => options.max_filesize = Some(parse_size(spec, value)? as u64),
+        "follow" => options.follow = on,
+        "one-file-system" => options.one_file_system = on,
+        "hidden" => options.hidden = on,
+        "no-ignore" => options.no_ignore = true,
+        "ignore-file" => {
+            let base = env::current_dir().and_then(fs::canonicalize).map_err(|e| e.to_string())?;
+            let rules = IgnoreRules::from_file(Path::new(value), &base)
+                .map_err(|e| format!("couldn't read ignore file {}: {}", value, e))?;
+            options.ignore_files.push(rules);
+        }
+        "git-tracked" => {
+            options.git_tracked = on;
+            options.recursive |= on;
+        }
+        "threads" => pending.threads = Some(parse_number(spec, value)?),
+        "mmap" => options.mmap = on,
+        "no-mmap" => options.mmap = false,
+        "no-sort" => options.no_sort = true,
+        "files" => {
+            options.files = on;
+            options.recursive |= on;
+        }
+        "filename-only" => {
+            options.filename_only = on;
+            options.recursive |= on;
+        }
+        "sort" | "sortr" => {
+            options.sort = SortKey::parse(spec.long, value)?;
+            options.sort_reverse = spec.long == "sortr";
+        }
+        "rev" => options.rev = Some(value.to_string()),
+        "files-from" => options.files_from = Some(value.to_string()),
+        "files-from-null" => options.files_from_null = on,
+        "with-filename" => pending.with_filename = Some(on),
+        "no-filename" => pending.with_filename = Some(false),
+        "null-data" => options.null_data = on,
+        "crlf" => options.crlf = on,
+        "null" => options.null = on,
+        "files-with-matches" => options.files_with_matches = on,
+        "files-without-match" => options.files_without_match = on,
+        "verbose" => options.verbose = on,
+        "no-config" => {}
+        "help" => pending.help = on,
+        "version" => pending.version = on,
+        _ => unreachable!("option --{} has no handler", spec.long),
+    }
+    Ok(())
+}
+
+// Returns the option and whether it's turned on, which it isn't for
+// --no-FLAG, where FLAG is any option without a value and --no-FLAG isn't
+// an option of its own.
+fn find_long(name: &str) -> Result<(&'static OptionSpec, bool), String> {
+    if let Some(spec) = OPTIONS.iter().find(|spec| spec.long == name) {
+        return Ok((spec, true));
+    }
+    name.strip_prefix("no-")
+        .and_then(|flag| OPTIONS.iter().find(|spec| spec.long == flag && spec.value.is_none() && !flag.starts_with("no-")))
+        .map(|spec| (spec, false))
+        .ok_or_else(|| format!("unknown option: --{}", name))
+}
+
+fn find_short(short: char) -> Result<&'static OptionSpec, String> {
+    OPTIONS.iter()
+        .find(|spec| spec.short == Some(short))
+        .ok_or_else(|| format!("unknown option: -{}", short))
+}
+
+pub fn parse_args(args: &[String]) -> Result<Action, String> {
+    let mut options = MinigrepOptions {
+        context_separator: Some("--".to_string()),
+        capture_delimiter: ",".to_string(),
+        ..Default::default()
+    };
+    let mut pending = PendingOptions::default();
+    let mut positionals: Vec<&String> = Vec::new();
+
+    let mut arg_index = 1;
+    while arg_index < args.len() {
+        let arg = &args[arg_index];
+        arg_index += 1;
+
+        if arg == "--" {
+            positionals.extend(&args[arg_index..]);
+            break;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline_value) = match long.find('=') {
+                Some(eq) => (&long[..eq], Some(&long[eq + 1..])),
+                None => (long, None),
+            };
+            let (spec, on) = find_long(name)?;
+            let value = match (spec.value, inline_value) {
+                (None, Some(_)) => return Err(format!("option --{} doesn't take a value", name)),
+                (None, None) => None,
+                (Some(_), Some(value)) => Some(value),
+                (Some(_), None) => {
+                    let value = args.get(arg_index)
+                        .ok_or_else(|| format!("missing value for --{}", name))?;
+                    arg_index += 1;
+                    Some(value.as_str())
+                }
+            };
+            apply_option(&mut options, &mut pending, spec, value, on)?;
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // Short flags may be combined ("-vn"), and a short option that takes
+            // a value uses the rest of the argument ("-m5") or the next one.
+            for (char_index, short) in arg[1..].char_indices() {
+                let spec = find_short(short)?;
+                if spec.value.is_none() {
+                    apply_option(&mut options, &mut pending, spec, None, true)?;
+                    continue;
+                }
+                let rest = &arg[1 + char_index + short.len_utf8()..];
+                let value = if !rest.is_empty() {
+                    rest
+                } else {
+                    let value = args.get(arg_index)
+                        .ok_or_else(|| format!("missing value for -{}", short))?;
+                    arg_index += 1;
+                    value.as_str()
+                };
+                apply_option(&mut options, &mut pending, spec, Some(value), true)?;
+                break;
+            }
+        } else {
+            positionals.push(arg);
+        }
+    }
+
+    if pending.help {
+        return Ok(Action::Help);
+    }
+    if pending.version {
+        return Ok(Action::Version);
+    }
+    if pending.type_list {
+        return Ok(Action::TypeList(pending.file_types.list()));
+    }
+
+    if options.write && options.replace.is_none() {
+        return Err("--write requires --replace".to_string());
+    }
+    if options.diff && options.replace.is_none() {
+        return Err("--diff requires --replace".to_string());
+    }
+    if (options.write || options.diff) && options.encoding.is_some() {
+        return Err("--encoding can't be used with --write or --diff".to_string());
+    }
+    if options.hex && options.encoding.is_some() {
+        return Err("--hex reads raw bytes and can't be used with --encoding".to_string());
+    }
+
+    for name in &pending.types {
+        options.types.extend(pending.file_types.globs(name)?);
+    }
+    for name in &pending.types_not {
+        options.types_not.extend(pending.file_types.globs(name)?);
+    }
+
+    let mut positionals = positionals.into_iter();
+    if !pending.patterns_given && !options.files {
+        let query = positionals.next().ok_or("missing query")?;
+        options.patterns.push(query.to_string());
+    }
+    options.filenames = positionals.map(|f| f.to_string()).collect();
+    if options.filenames.is_empty() && options.files_from.is_none() {
+        let default = if options.recursive || options.rev.is_some() { "." } else { "-" };
+        options.filenames.push(default.to_string());
+    }
+
+    options.threads = match pending.threads {
+        Some(0) | None => thread::available_parallelism().map_or(1, usize::from),
+        Some(threads) => threads,
+    };
+    options.after_context = pending.after_context.or(pending.context).unwrap_or(0);
+    options.before_context = pending.before_context.or(pending.context).unwrap_or(0);
+    // Half of --max-memory is for reading, shared by the threads searching,
+    // and half for output waiting to be printed. The lines kept for leading
+    // context share the reading part with the line being read.
+    if let Some(budget) = options.max_memory {
+        options.threads = options.threads.min(budget / 2 / MIN_READ_MEMORY).max(1);
+        let line_size = read_memory(&options).unwrap_or_default() / (options.before_context + 1);
+        options.max_line_size = Some(options.max_line_size.map_or(line_size, |size| size.min(line_size)));
+    }
+    options.heading = !options.vimgrep && !options.json && !options.captures_json && options.format.is_none()
+        && pending.heading.unwrap_or_else(|| io::stdout().is_terminal());
+    options.line_buffered = pending.line_buffered.unwrap_or_else(|| io::stdout().is_terminal());
+    options.color = !options.json && !options.captures_json && pending.color.unwrap_or(ColorChoice::Auto).enabled();
+    if options.json {
+        options.context_separator = None;
+    }
+    options.with_filename = options.vimgrep || pending.with_filename.unwrap_or(
+        options.recursive || options.archives || options.rev.is_some() || options.files_from.is_some() || options.filenames.len() > 1
+    );
+
+    Ok(Action::Search(Box::new(options)))
+}
+
+// Splits a line into arguments the way a shell would for simple cases:
+// whitespace separates arguments, quotes group them and backslash escapes the
+// next character outside single quotes.
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => current.push(c),
+            (_, '\\') => {
+                let escaped = chars.next().ok_or("trailing backslash")?;
+                current.push(escaped);
+                in_arg = true;
+            }
+            (Some(_), _) => current.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, _) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_string());
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("MINIGREP_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    let path = config_home.join("minigrep").join("config");
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// The config file holds options with the same syntax as the command line, one
+// or more per line; blank lines and lines starting with # are ignored. A
+// flag it gives can be turned off for one search with --no-FLAG.
+fn config_args() -> Result<Vec<String>, String> {
+    let path = match config_path() {
+        Some(path) if !path.as_os_str().is_empty() => path,
+        _ => return Ok(Vec::new()),
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("couldn't read config file {}: {}", path.display(), e))?;
+    let mut args = Vec::new();
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let line_args = split_args(trimmed)
+            .map_err(|e| format!("error in config file {}: {}", path.display(), e))?;
+        args.extend(line_args);
+    }
+    Ok(args)
+}
+
+fn env_args() -> Result<Vec<String>, String> {
+    match env::var("MINIGREP_OPTS") {
+        Ok(value) => split_args(&value).map_err(|e| format!("error in MINIGREP_OPTS: {}", e)),
+        Err(env::VarError::NotPresent) => Ok(Vec::new()),
+        Err(env::VarError::NotUnicode(_)) => Err("MINIGREP_OPTS is not valid UTF-8".to_string()),
+    }
+}
+
+// Default options go before the command line arguments so that anything given
+// explicitly is applied last and wins.
+pub fn parse_command_line(args: &[String]) -> Result<Action, String> {
+    // `completions SHELL` prints a completion script, but only for a shell it
+    // knows; otherwise it's a search for "completions" in the file named SHELL.
+    if args.len() == 3 && args[1] == "completions" && crate::completions::SHELLS.contains(&args[2].as_str()) {
+        return Ok(Action::Completions(args[2].clone()));
+    }
+    // `history` searches the lines each commit added or removed instead of
+    // files; the arguments after it are the usual options, query and paths.
+    let history = args.get(1).is_some_and(|arg| arg == "history");
+    let mut merged: Vec<String> = args[..1].to_vec();
+    let explicit = &args[1 + history as usize..];
+    let options_end = explicit.iter().position(|arg| arg == "--").unwrap_or(explicit.len());
+    if !explicit[..options_end].iter().any(|arg| arg == "--no-config") {
+        merged.extend(config_args()?);
+    }
+    merged.extend(env_args()?);
+    merged.extend_from_slice(explicit);
+    let mut action = parse_args(&merged)?;
+    if let (true, Action::Search(options)) = (history, &mut action) {
+        options.history = true;
+        options.filenames.retain(|path| path != "-");
+    }
+    Ok(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Mutex;
+
+    use super::{parse_command_line, Action};
+    use crate::archive::tests::TempFile;
+    use crate::MinigrepOptions;
+
+    // Held by tests that set the variables default options are read from.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn search_options(args: &[&str]) -> MinigrepOptions {
+        let args: Vec<String> = ["minigrep"].iter().chain(args).map(|arg| arg.to_string()).collect();
+        match parse_command_line(&args) {
+            Ok(Action::Search(options)) => *options,
+            Ok(_) => panic!("{:?} isn't a search", args),
+            Err(e) => panic!("{:?}: {}", args, e),
+        }
+    }
+
+    #[test]
+    fn arguments_override_minigrep_opts() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        env::set_var("MINIGREP_CONFIG", "");
+        env::set_var("MINIGREP_OPTS", "--no-color --line-number --heading --hidden");
+        let options = search_options(&["needle"]);
+        assert!(!options.color && options.line_number && options.heading && options.hidden);
+        let options = search_options(&["--color", "always", "--no-line-number", "--no-heading", "--no-hidden", "needle"]);
+        assert!(options.color && !options.line_number && !options.heading && !options.hidden);
+        env::remove_var("MINIGREP_OPTS");
+    }
+
+    #[test]
+    fn completions_only_for_known_shells() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(matches!(
+            parse_command_line(&args(&["minigrep", "completions", "zsh"])),
+            Ok(Action::Completions(shell)) if shell == "zsh"
+        ));
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        env::set_var("MINIGREP_CONFIG", "");
+        let options = search_options(&["completions", "notes.txt"]);
+        assert_eq!((options.patterns, options.filenames), (vec!["completions".to_string()], vec!["notes.txt".to_string()]));
+    }
+
+    #[test]
+    fn arguments_override_the_config_file() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let config = TempFile::new("config", b"# defaults\n-n --hidden\n--context 2\n");
+        env::set_var("MINIGREP_CONFIG", config.name());
+        env::remove_var("MINIGREP_OPTS");
+        let options = search_options(&["needle"]);
+        assert!(options.line_number && options.hidden && options.after_context == 2);
+        let options = search_options(&["--no-line-number", "-C", "0", "needle"]);
+        assert!(!options.line_number && options.hidden && options.after_context == 0);
+        env::set_var("MINIGREP_OPTS", "--no-hidden");
+        let options = search_options(&["needle"]);
+        assert!(options.line_number && !options.hidden);
+        env::remove_var("MINIGREP_OPTS");
+        let options = search_options(&["--no-config", "needle"]);
+        assert!(!options.line_number && !options.hidden && options.after_context == 0);
+        env::remove_var("MINIGREP_CONFIG");
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use crate::encoding;
+use crate::matcher::Matcher;
+use crate::mmap::Mmap;
+use crate::output::{self, Capture};
+use crate::parallel;
+use crate::stats::Stats;
+use crate::{
+    json, print_count, print_heading, search_input, shows_line_numbers, stops_at_first_match, uses_context, warn, Chunk,
+    Input, MinigrepOptions,
+};
+
+// A large file is searched as pieces of about this size, several at once,
+// each starting at the beginning of a line.
+#[cfg(not(test))]
+const CHUNK_SIZE: u64 = 16 << 20;
+// Tests split small files, so that many of their lines lie across the edge
+// of a piece.
+#[cfg(test)]
+const CHUNK_SIZE: u64 = 100;
+
+// Smaller files are searched whole, as splitting them wouldn't pay off.
+const MIN_SPLIT_SIZE: u64 = 4 * CHUNK_SIZE;
+
+// Whether a file can be searched in pieces with these options. Each line is
+// then matched without seeing any other, so context lines, -U and -m, which
+// need the lines around a match or before it, rule it out. So do quiet
+// searches and -l, which only read up to the first match anyway. A file
+// found among others is left whole, as the threads are searching those.
+fn splittable(options: &MinigrepOptions) -> bool {
+    options.threads > 1
+        && !parallel::in_worker()
+        && !options.multiline
+        && !uses_context(options)
+        && options.max_count.is_none()
+        && !stops_at_first_match(options)
+        && options.encoding.is_none()
+}
+
+// Searches a large regular file as pieces on `options.threads` threads and
+// prints their output in order, as if it had been searched from start to
+// end. Returns None, leaving the file to be searched as usual, if it's too
+// small, the options don't allow it, or it starts with a byte order mark or
+// looks binary.
+pub fn search_file(options: &MinigrepOptions, matcher: &Matcher, filename: &str, start: Instant) -> Option<Stats> {
+    if filename == "-" || !splittable(options) {
+        return None;
+    }
+    let mut file = File::open(filename).ok()?;
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() < MIN_SPLIT_SIZE {
+        return None;
+    }
+    let mut head = [0u8; 8192];
+    let head_len = file.read(&mut head).ok()?;
+    let head = &head[..head_len];
+    if encoding::sniff_bom(head).is_some() || (!options.text && !options.null_data && head.contains(&0)) {
+        return None;
+    }
+
+    let terminator = if options.null_data { b'\0' } else { b'\n' };
+    let starts = match chunk_starts(&mut file, metadata.len(), terminator) {
+        Ok(starts) => starts,
+        Err(e) => {
+            warn(options, &format!("error reading {}: {}", filename, e));
+            return Some(Stats::default());
+        }
+    };
+    let range = |i: usize| (starts[i], starts.get(i + 1).copied().unwrap_or(metadata.len()));
+    // With --mmap the file is mapped once and every piece read from the map.
+    let map = if options.mmap { Mmap::open(filename) } else { None };
+    let open_range = |range| open_range(filename, map.as_ref(), range);
+
+    // Line numbers are only worked out when they're printed, since that
+    // takes a pass over the file of its own.
+    let mut line_numbers = vec![0; starts.len()];
+    if shows_line_numbers(options) {
+        let mut lines_before = 0;
+        let mut failed = None;
+        in_order(options.threads, starts.len(), &|i| count_lines(open_range(range(i))?, terminator), &mut |i, lines| {
+            match lines {
+                Ok(lines) => {
+                    line_numbers[i] = lines_before;
+                    lines_before += lines;
+                    true
+                }
+                Err(e) => {
+                    failed = Some(e);
+                    false
+                }
+            }
+        });
+        if let Some(e) = failed {
+            warn(options, &format!("error reading {}: {}", filename, e));
+            return Some(Stats::default());
+        }
+    }
+
+    let search_chunk = |i: usize, binary: bool| -> io::Result<(Stats, Capture, Chunk)> {
+        let mut chunk = Chunk {
+            line_number: line_numbers[i],
+            offset: range(i).0 as usize,
+            count: 0,
+            binary,
+            stopped: false,
+        };
+        let input = open_range(range(i))?;
+        let (stats, captured) = output::capture(|| {
+            search_input(options, matcher, filename, input, Instant::now(), Some(&mut chunk))
+        });
+        Ok((stats, captured, chunk))
+    };
+    let printed_before = json::bytes_printed();
+    let mut heading_printed = false;
+    let mut total = Stats::default();
+    let mut count = 0;
+    let mut binary = false;
+    in_order(options.threads, starts.len(), &|i| search_chunk(i, false), &mut |i, result| {
+        // Pieces are searched before it's known whether an earlier one has a
+        // NUL byte, so one that printed lines of a file that turned out to be
+        // binary is searched again, to print that the file matches instead.
+        let result = match result {
+            Ok((_, captured, _)) if binary && !captured.is_empty() => search_chunk(i, true),
+            result => result,
+        };
+        let (stats, captured, chunk) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                warn(options, &format!("error reading {}: {}", filename, e));
+                return false;
+            }
+        };
+        if !captured.is_empty() {
+            // A piece that only printed the note that a binary file matches
+            // doesn't print the heading, as the file searched whole wouldn't.
+            if !(chunk.stopped && stats.matched_lines == 1) {
+                print_heading(options, filename, &mut heading_printed);
+            }
+            output::write_captured(captured);
+        }
+        total.add(&stats);
+        count += chunk.count;
+        binary |= chunk.binary;
+        !chunk.stopped
+    });
+
+    let file_stats = Stats {
+        elapsed: start.elapsed(),
+        searches: 1,
+        searches_with_match: (total.matched_lines > 0) as usize,
+        bytes_printed: json::bytes_printed() - printed_before,
+        ..total
+    };
+    if heading_printed && options.json {
+        json::emit(&json::end(filename, &file_stats));
+    }
+    print_count(options, filename, count);
+    Some(file_stats)
+}
+
+// Where each piece of a file of `len` bytes starts: at 0, then at the first
+// line that starts at or after CHUNK_SIZE bytes into the previous piece.
+fn chunk_starts(file: &mut File, len: u64, terminator: u8) -> io::Result<Vec<u64>> {
+    let mut starts = vec![0];
+    let mut at = CHUNK_SIZE;
+    while at < len {
+        // Reading from the byte before finds a line that starts right at `at`.
+        file.seek(SeekFrom::Start(at - 1))?;
+        let line_start = at - 1 + skip_line(&mut BufReader::new(&mut *file), terminator)?;
+        if line_start >= len {
+            break;
+        }
+        starts.push(line_start);
+        at = line_start + CHUNK_SIZE;
+    }
+    Ok(starts)
+}
+
+// Reads past the next terminator, returning how many bytes that took. A
+// long line is never held in memory whole.
+fn skip_line(reader: &mut dyn BufRead, terminator: u8) -> io::Result<u64> {
+    let mut skipped = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(skipped);
+        }
+        if let Some(i) = buf.iter().position(|&b| b == terminator) {
+            return Ok(skipped + i as u64 + 1);
+        }
+        skipped += buf.len() as u64;
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+fn open_range<'a>(filename: &str, map: Option<&'a Mmap>, (start, end): (u64, u64)) -> io::Result<Input<'a>> {
+    if let Some(map) = map {
+        return Ok(Input::Mapped(&map.bytes()[start as usize..end as usize]));
+    }
+    let mut file = File::open(filename)?;
+    file.seek(SeekFrom::Start(start))?;
+    Ok(Input::Reader(Box::new(BufReader::new(file.take(end - start)))))
+}
+
+fn count_lines(mut input: Input, terminator: u8) -> io::Result<usize> {
+    let reader = input.as_reader();
+    let mut lines = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(lines);
+        }
+        lines += buf.iter().filter(|&&b| b == terminator).count();
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+// Runs `work` for each of `jobs` indexes on up to `threads` threads and hands
+// the results to `each` in index order, stopping once it returns false.
+fn in_order<T: Send>(
+    threads: usize,
+    jobs: usize,
+    work: &(dyn Fn(usize) -> T + Sync),
+    each: &mut dyn FnMut(usize, T) -> bool,
+) {
+    let next_job = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, results) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.min(jobs) {
+            let (next_job, stop, sender) = (&next_job, &stop, sender.clone());
+            scope.spawn(move || loop {
+                let i = next_job.fetch_add(1, Ordering::Relaxed);
+                if i >= jobs || stop.load(Ordering::Relaxed) || sender.send((i, work(i))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        // Results that came in before those of earlier jobs.
+        let mut waiting = BTreeMap::new();
+        let mut next_index = 0;
+        for (i, result) in results {
+            waiting.insert(i, result);
+            while let Some(result) = waiting.remove(&next_index) {
+                if !each(next_index, result) {
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                }
+                next_index += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::time::Instant;
+
+    use regex::Regex;
+
+    use super::{search_file, MIN_SPLIT_SIZE};
+    use crate::archive::tests::TempFile;
+    use crate::matcher::{Matcher, MatcherConfig};
+    use crate::{output, search_input, Input, MinigrepOptions};
+
+    // Lines of different lengths, every third one holding "needle".
+    fn haystack() -> Vec<u8> {
+        let mut contents = String::new();
+        for i in 0..2000 {
+            let word = if i % 3 == 0 { "needle " } else { "hay " };
+            contents.push_str(&format!("{} {}\n", i, word.repeat(i % 7 + 1)));
+        }
+        contents.into_bytes()
+    }
+
+    // What searching `contents` for `query` prints when it's split into
+    // pieces, and when it's searched whole. Times and the byte counts of
+    // output printed by other tests at once are left out.
+    fn split_and_whole(name: &str, options: MinigrepOptions, query: &str, contents: &[u8]) -> (String, String) {
+        assert!(contents.len() as u64 >= MIN_SPLIT_SIZE);
+        let file = TempFile::new(name, contents);
+        let options = MinigrepOptions { threads: 4, ..options };
+        let matcher = Matcher::new(&[query.to_string()], &MatcherConfig::default()).unwrap();
+        let (split, split_output) = output::capture(|| search_file(&options, &matcher, file.name(), Instant::now()));
+        assert!(split.is_some(), "{} wasn't split", name);
+        let input = Input::Reader(Box::new(BufReader::new(File::open(file.name()).unwrap())));
+        let (_, whole_output) =
+            output::capture(|| search_input(&options, &matcher, file.name(), input, Instant::now(), None));
+        let varying = Regex::new(r#""elapsed":\{[^}]*\}|"bytes_printed":\d+"#).unwrap();
+        let normalize = |captured: output::Capture| {
+            varying.replace_all(&captured.into_string().replace(file.name(), "file"), "").into_owned()
+        };
+        (normalize(split_output), normalize(whole_output))
+    }
+
+    #[test]
+    fn line_numbers() {
+        let options = MinigrepOptions {
+            line_number: true,
+            ..MinigrepOptions::default()
+        };
+        let (split, whole) = split_and_whole("line-numbers", options, "needle", &haystack());
+        assert!(whole.starts_with("1:0 needle \n4:3 needle needle needle needle \n"));
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn captures_json_line_numbers() {
+        let options = MinigrepOptions {
+            captures_json: true,
+            ..MinigrepOptions::default()
+        };
+        let (split, whole) = split_and_whole("captures-json", options, r"(?P<number>\d+) needle", &haystack());
+        assert!(whole.contains("{\"file\":\"file\",\"line\":1000,\"number\":\"999\"}\n"));
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn mapped_pieces() {
+        let options = MinigrepOptions {
+            line_number: true,
+            mmap: true,
+            ..MinigrepOptions::default()
+        };
+        let (split, whole) = split_and_whole("mapped", options, "needle", &haystack());
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn json() {
+        let options = MinigrepOptions {
+            json: true,
+            ..MinigrepOptions::default()
+        };
+        let (split, whole) = split_and_whole("json", options, "needle", &haystack());
+        assert!(whole.contains("\"lines\":{\"text\":\"999 needle needle needle needle needle needle \\n\"},\"line_number\":1000,"));
+        assert!(whole.ends_with("\"matched_lines\":667,\"matches\":2665}}}\n"));
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn counts() {
+        for count_matches in [false, true] {
+            let options = MinigrepOptions {
+                count: !count_matches,
+                count_matches,
+                ..MinigrepOptions::default()
+            };
+            let (split, whole) = split_and_whole("counts", options, "needle", &haystack());
+            assert_eq!(whole, if count_matches { "2665\n" } else { "667\n" });
+            assert_eq!(split, whole);
+        }
+    }
+
+    // Lines of 37 bytes, so that pieces of about 100 bytes end in the middle
+    // of most of them, each with a match at its start and one at its end.
+    #[test]
+    fn matches_on_lines_across_the_edges() {
+        let contents: String = (0..100).map(|i| format!("needle {:022} needle\n", i)).collect();
+        let options = MinigrepOptions {
+            line_number: true,
+            column: true,
+            byte_offset: true,
+            only_matching: true,
+            ..MinigrepOptions::default()
+        };
+        let (split, whole) = split_and_whole("edges", options, "needle", contents.as_bytes());
+        assert_eq!(whole.lines().count(), 200);
+        assert!(whole.contains("\n3:1:74:needle\n3:31:104:needle\n"));
+        assert_eq!(split, whole);
+    }
+
+    // The NUL byte is past the head of the file checked before it's split, in a
+    // line that doesn't match, so later pieces print the note instead.
+    #[test]
+    fn binary_after_the_head() {
+        let mut contents = haystack();
+        let line = contents.windows(6).position(|window| window == b"\n1001 ").unwrap() + 1;
+        contents[line + 5] = 0;
+        for heading in [false, true] {
+            let options = MinigrepOptions {
+                line_number: true,
+                with_filename: heading,
+                heading,
+                ..MinigrepOptions::default()
+            };
+            let (split, whole) = split_and_whole("binary", options, "needle", &contents);
+            assert!(whole.ends_with("999 needle needle needle needle needle needle \nbinary file file matches\n"));
+            assert_eq!(split, whole);
+        }
+        let options = MinigrepOptions {
+            with_filename: true,
+            heading: true,
+            ..MinigrepOptions::default()
+        };
+        let (split, whole) = split_and_whole("binary-only", options, "1998 needle", &contents);
+        assert_eq!(whole, "binary file file matches\n");
+        assert_eq!(split, whole);
+    }
+}
@@ -0,0 +1,194 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Result<ColorChoice, String> {
+        match value {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("invalid value for --color: {} (expected auto, always or never)", value)),
+        }
+    }
+
+    // Auto mode colors only when stdout is a terminal and the user hasn't opted
+    // out through NO_COLOR (https://no-color.org) or a dumb terminal.
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                let dumb = env::var_os("TERM").is_some_and(|term| term == "dumb");
+                io::stdout().is_terminal() && !no_color && !dumb
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Style {
+    Path,
+    LineNumber,
+    Column,
+    Match,
+}
+
+#[derive(Clone, Copy)]
+enum Color {
+    Named(u8),
+    Ansi256(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn parse(value: &str) -> Result<Color, String> {
+        let names = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+        if let Some(index) = names.iter().position(|name| *name == value) {
+            return Ok(Color::Named(index as u8));
+        }
+        let invalid = || format!("invalid color: {}", value);
+        let parts: Vec<&str> = value.split(',').collect();
+        match parts.as_slice() {
+            [n] => n.trim().parse().map(Color::Ansi256).map_err(|_| invalid()),
+            [r, g, b] => {
+                let component = |c: &str| c.trim().parse::<u8>().map_err(|_| invalid());
+                Ok(Color::Rgb(component(r)?, component(g)?, component(b)?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    // `base` is 30 for foreground colors and 40 for background colors.
+    fn code(&self, base: u8) -> String {
+        match *self {
+            Color::Named(n) => (base + n).to_string(),
+            Color::Ansi256(n) => format!("{};5;{}", base + 8, n),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct ColorSpec {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    intense: bool,
+    underline: bool,
+}
+
+impl ColorSpec {
+    fn fg(color: u8) -> ColorSpec {
+        ColorSpec {
+            fg: Some(Color::Named(color)),
+            ..Default::default()
+        }
+    }
+
+    fn escape_code(&self) -> String {
+        let mut codes = Vec::new();
+        if self.bold {
+            codes.push("1".to_string());
+        }
+        if self.underline {
+            codes.push("4".to_string());
+        }
+        if let Some(fg) = self.fg {
+            match fg {
+                Color::Named(n) if self.intense => codes.push((90 + n).to_string()),
+                _ => codes.push(fg.code(30)),
+            }
+        }
+        if let Some(bg) = self.bg {
+            codes.push(bg.code(40));
+        }
+        if codes.is_empty() {
+            return String::new();
+        }
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+// The colors used for each kind of output, adjustable with --colors.
+pub struct Palette {
+    path: ColorSpec,
+    line_number: ColorSpec,
+    column: ColorSpec,
+    matched: ColorSpec,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            path: ColorSpec::fg(5),
+            line_number: ColorSpec::fg(2),
+            column: ColorSpec::fg(2),
+            matched: ColorSpec {
+                bold: true,
+                ..ColorSpec::fg(1)
+            },
+        }
+    }
+}
+
+impl Palette {
+    fn spec_mut(&mut self, name: &str) -> Result<&mut ColorSpec, String> {
+        match name {
+            "path" => Ok(&mut self.path),
+            "line" => Ok(&mut self.line_number),
+            "column" => Ok(&mut self.column),
+            "match" => Ok(&mut self.matched),
+            _ => Err(format!("unknown color target: {} (expected path, line, column or match)", name)),
+        }
+    }
+
+    // Applies one --colors setting of the form TARGET:fg:COLOR, TARGET:bg:COLOR,
+    // TARGET:style:STYLE or TARGET:none. COLOR is a color name, a 256-color
+    // index or an r,g,b triple.
+    pub fn apply(&mut self, setting: &str) -> Result<(), String> {
+        let parts: Vec<&str> = setting.split(':').collect();
+        let invalid = || format!("invalid --colors setting: {}", setting);
+        match parts.as_slice() {
+            [target, "none"] => {
+                *self.spec_mut(target)? = ColorSpec::default();
+            }
+            [target, "fg", color] => self.spec_mut(target)?.fg = Some(Color::parse(color)?),
+            [target, "bg", color] => self.spec_mut(target)?.bg = Some(Color::parse(color)?),
+            [target, "style", style] => {
+                let spec = self.spec_mut(target)?;
+                match *style {
+                    "bold" => spec.bold = true,
+                    "nobold" => spec.bold = false,
+                    "intense" => spec.intense = true,
+                    "nointense" => spec.intense = false,
+                    "underline" => spec.underline = true,
+                    "nounderline" => spec.underline = false,
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(invalid()),
+        }
+        Ok(())
+    }
+
+    pub fn paint(&self, style: Style, text: &str) -> String {
+        let spec = match style {
+            Style::Path => &self.path,
+            Style::LineNumber => &self.line_number,
+            Style::Column => &self.column,
+            Style::Match => &self.matched,
+        };
+        let code = spec.escape_code();
+        if code.is_empty() {
+            return text.to_string();
+        }
+        format!("{}{}\x1b[0m", code, text)
+    }
+}
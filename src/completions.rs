@@ -0,0 +1,129 @@
+use std::fmt::Write;
+
+use crate::args::{OptionSpec, OPTIONS};
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+pub fn generate(shell: &str) -> Result<String, String> {
+    match shell {
+        "bash" => Ok(bash()),
+        "zsh" => Ok(zsh()),
+        "fish" => Ok(fish()),
+        "powershell" => Ok(powershell()),
+        _ => Err(format!("unsupported shell: {} (expected one of {})", shell, SHELLS.join(", "))),
+    }
+}
+
+fn takes_file(spec: &OptionSpec) -> bool {
+    spec.value == Some("FILE")
+}
+
+fn option_names(spec: &OptionSpec) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some(short) = spec.short {
+        names.push(format!("-{}", short));
+    }
+    names.push(format!("--{}", spec.long));
+    names
+}
+
+fn bash() -> String {
+    let all: Vec<String> = OPTIONS.iter().flat_map(option_names).collect();
+    let file_values: Vec<String> = OPTIONS.iter()
+        .filter(|spec| takes_file(spec))
+        .flat_map(option_names)
+        .collect();
+    let other_values: Vec<String> = OPTIONS.iter()
+        .filter(|spec| spec.value.is_some() && !takes_file(spec))
+        .flat_map(option_names)
+        .collect();
+
+    let mut out = String::new();
+    out.push_str("_minigrep() {\n");
+    out.push_str("    local cur prev\n");
+    out.push_str("    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    out.push_str("    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    out.push_str("    case \"$prev\" in\n");
+    let _ = writeln!(out, "        {})", file_values.join("|"));
+    out.push_str("            COMPREPLY=( $(compgen -f -- \"$cur\") )\n");
+    out.push_str("            return;;\n");
+    let _ = writeln!(out, "        {})", other_values.join("|"));
+    out.push_str("            COMPREPLY=()\n");
+    out.push_str("            return;;\n");
+    out.push_str("    esac\n");
+    out.push_str("    if [[ \"$cur\" == -* ]]; then\n");
+    let _ = writeln!(out, "        COMPREPLY=( $(compgen -W \"{}\" -- \"$cur\") )", all.join(" "));
+    out.push_str("    else\n");
+    out.push_str("        COMPREPLY=( $(compgen -f -- \"$cur\") )\n");
+    out.push_str("    fi\n");
+    out.push_str("}\n");
+    out.push_str("complete -F _minigrep -o filenames minigrep\n");
+    out
+}
+
+fn zsh_escape(help: &str) -> String {
+    help.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh() -> String {
+    let mut out = String::new();
+    out.push_str("#compdef minigrep\n\n");
+    out.push_str("_arguments -s \\\n");
+    for spec in OPTIONS {
+        let help = zsh_escape(spec.help);
+        let value = match spec.value {
+            Some(name) if takes_file(spec) => format!(":{}:_files", name.to_lowercase()),
+            Some(name) => format!(":{}:", name.to_lowercase()),
+            None => String::new(),
+        };
+        for name in option_names(spec) {
+            let _ = writeln!(out, "  '{}[{}]{}' \\", name, help, value);
+        }
+    }
+    out.push_str("  '1:query:' \\\n");
+    out.push_str("  '*:file:_files'\n");
+    out
+}
+
+fn fish() -> String {
+    let mut out = String::new();
+    for spec in OPTIONS {
+        let _ = write!(out, "complete -c minigrep");
+        if let Some(short) = spec.short {
+            let _ = write!(out, " -s {}", short);
+        }
+        let _ = write!(out, " -l {}", spec.long);
+        if spec.value.is_some() {
+            out.push_str(" -r");
+            if takes_file(spec) {
+                out.push_str(" -F");
+            }
+        }
+        let _ = writeln!(out, " -d '{}'", spec.help.replace('\'', "\\'"));
+    }
+    out
+}
+
+fn powershell() -> String {
+    let mut out = String::new();
+    out.push_str("Register-ArgumentCompleter -Native -CommandName minigrep -ScriptBlock {\n");
+    out.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    out.push_str("    $options = @(\n");
+    for spec in OPTIONS {
+        let help = spec.help.replace('\'', "''");
+        for name in option_names(spec) {
+            let _ = writeln!(
+                out,
+                "        [System.Management.Automation.CompletionResult]::new('{}', '{}', 'ParameterName', '{}')",
+                name, name, help
+            );
+        }
+    }
+    out.push_str("    )\n");
+    out.push_str("    $options | Where-Object { $_.CompletionText -like \"$wordToComplete*\" }\n");
+    out.push_str("}\n");
+    out
+}
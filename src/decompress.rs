@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::process;
+
+// The formats --search-zip knows, by extension and by the magic bytes they
+// start with, and the command that decompresses each to standard output.
+const FORMATS: &[(&[&str], &[u8], &[&str])] = &[
+    (&["gz", "tgz"], b"\x1f\x8b", &["gzip", "-dc"]),
+    (&["bz2", "tbz2"], b"BZh", &["bzip2", "-dc"]),
+    (&["xz", "txz", "lzma"], b"\xfd7zXZ\x00", &["xz", "-dc"]),
+    (&["zst", "zstd"], b"\x28\xb5\x2f\xfd", &["zstd", "-dcq"]),
+];
+
+fn command_for(path: &Path) -> io::Result<Option<&'static [&'static str]>> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if let Some((_, _, command)) = FORMATS.iter().find(|(extensions, _, _)| extensions.contains(&extension)) {
+        return Ok(Some(command));
+    }
+    let mut magic = [0; 6];
+    let mut read = 0;
+    let mut file = File::open(path)?;
+    while read < magic.len() {
+        match file.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(FORMATS.iter()
+        .find(|(_, start, _)| magic[..read].starts_with(start))
+        .map(|(_, _, command)| *command))
+}
+
+// Opens `path` through the matching decompression command, or returns None
+// if the file isn't compressed in a known format.
+pub fn open(path: &str) -> io::Result<Option<Box<dyn BufRead>>> {
+    let command = match command_for(Path::new(path))? {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let mut decompress = Command::new(command[0]);
+    decompress.args(&command[1..]).arg(path).stdin(Stdio::null());
+    process::spawn_reader(decompress, command[0]).map(Some)
+}
@@ -0,0 +1,90 @@
+// Input encodings for --encoding. Input in any of these is decoded to UTF-8
+// before matching; bytes that aren't valid in the encoding become U+FFFD.
+// Multi-byte East Asian encodings such as Shift_JIS aren't among them, as
+// decoding them takes character tables far bigger than the rest of minigrep.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+// Windows-1252 differs from Latin-1 only in 0x80-0x9F, where it has
+// punctuation and a few letters instead of C1 control codes. The five bytes
+// it leaves undefined decode to the control code of the same value, as
+// Windows itself does.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+const SUPPORTED: &str = "utf-8, utf-16le, utf-16be, latin-1 or windows-1252";
+
+impl Encoding {
+    // Accepts the usual spellings of each label, ignoring case, dashes and
+    // underscores, so utf-16le, UTF16LE and utf_16_le are all the same.
+    pub fn parse(label: &str) -> Result<Encoding, String> {
+        let normalized: String = label.chars()
+            .filter(|c| !matches!(c, '-' | '_'))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match normalized.as_str() {
+            "utf8" => Ok(Encoding::Utf8),
+            "utf16" | "utf16le" | "ucs2" => Ok(Encoding::Utf16Le),
+            "utf16be" => Ok(Encoding::Utf16Be),
+            "latin1" | "iso88591" | "l1" => Ok(Encoding::Latin1),
+            "windows1252" | "cp1252" => Ok(Encoding::Windows1252),
+            "shiftjis" | "sjis" | "cp932" | "windows31j" | "eucjp" | "iso2022jp" | "gbk" | "gb2312" | "gb18030"
+            | "big5" | "euckr" | "cp949" => Err(format!(
+                "{} isn't supported; convert the input to UTF-8 first, e.g. with iconv (supported: {})",
+                label, SUPPORTED
+            )),
+            _ => Err(format!("unsupported encoding: {} (expected {})", label, SUPPORTED)),
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Windows1252 => bytes.iter()
+                .map(|&b| match b {
+                    0x80..=0x9f => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect(),
+        }
+    }
+}
+
+// Recognizes a byte order mark at the start of `bytes`, returning the
+// encoding it marks and its length.
+pub fn sniff_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    if bytes.starts_with(b"\xef\xbb\xbf") {
+        Some((Encoding::Utf8, 3))
+    } else if bytes.starts_with(b"\xff\xfe") {
+        Some((Encoding::Utf16Le, 2))
+    } else if bytes.starts_with(b"\xfe\xff") {
+        Some((Encoding::Utf16Be, 2))
+    } else {
+        None
+    }
+}
+
+// A trailing odd byte can't be a code unit and decodes to U+FFFD.
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    let mut text: String = char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+    if bytes.len() % 2 == 1 {
+        text.push(char::REPLACEMENT_CHARACTER);
+    }
+    text
+}
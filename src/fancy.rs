@@ -0,0 +1,1182 @@
+// A small backtracking regex engine for the patterns the regex crate rejects:
+// lookahead and lookbehind, backreferences and atomic groups. It explores
+// alternatives in order like PCRE, so it can take exponential time on
+// pathological patterns; the default engine remains the regex crate.
+//
+// Patterns are compiled to a program that is run with an explicit stack of
+// backtracking points, so a long line can't overflow the call stack. Only
+// lookarounds and atomic groups run their body as a nested search, which
+// recurses as deep as they are nested in the pattern.
+
+use std::cmp::Ordering;
+
+use regex_syntax::hir::{Class as HirClass, HirKind};
+
+#[derive(Clone, Copy, Default)]
+pub struct Flags {
+    pub case_insensitive: bool,
+    pub multi_line: bool,
+    pub dot_all: bool,
+    // \d, \w, \s, \b and case folding only know about ASCII, for --no-unicode.
+    pub no_unicode: bool,
+}
+
+// Sorted, non-overlapping character ranges.
+type Ranges = Vec<(char, char)>;
+
+#[derive(Clone)]
+enum ClassItem {
+    Range(char, char),
+    // A Perl or POSIX class; the flag is whether it's negated, as in \D or
+    // [:^alpha:].
+    Set(Ranges, bool),
+    Nested(Class),
+}
+
+#[derive(Clone)]
+struct Class {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+
+impl Class {
+    fn contains(&self, c: char, case_insensitive: bool, unicode: bool) -> bool {
+        let found = self.items.iter().any(|item| {
+            let test = |c: char| match item {
+                ClassItem::Range(lo, hi) => *lo <= c && c <= *hi,
+                ClassItem::Set(ranges, negated) => in_ranges(ranges, c) != *negated,
+                ClassItem::Nested(class) => class.contains(c, false, unicode),
+            };
+            test(c) || (case_insensitive && if unicode {
+                c.to_lowercase().any(test) || c.to_uppercase().any(test)
+            } else {
+                test(c.to_ascii_lowercase()) || test(c.to_ascii_uppercase())
+            })
+        });
+        found != self.negated
+    }
+}
+
+fn in_ranges(ranges: &[(char, char)], c: char) -> bool {
+    ranges.binary_search_by(|&(lo, hi)| {
+        if hi < c {
+            Ordering::Less
+        } else if lo > c {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }).is_ok()
+}
+
+// \d, \w and \s as the regex crate defines them, so a pattern means the same
+// whichever engine it ends up in.
+fn perl_ranges(class: char, unicode: bool) -> Ranges {
+    let hir = regex_syntax::ParserBuilder::new()
+        .unicode(unicode)
+        .utf8(false)
+        .build()
+        .parse(&format!("\\{}", class));
+    match hir.as_ref().map(|hir| hir.kind()) {
+        Ok(HirKind::Class(HirClass::Unicode(class))) => class.iter().map(|r| (r.start(), r.end())).collect(),
+        Ok(HirKind::Class(HirClass::Bytes(class))) => class.iter()
+            .map(|r| (r.start() as char, r.end() as char))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// The ASCII classes that can appear in brackets, like [[:alpha:]].
+fn posix_ranges(name: &str) -> Option<Ranges> {
+    let ranges: &[(char, char)] = match name {
+        "alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+        "alpha" => &[('A', 'Z'), ('a', 'z')],
+        "ascii" => &[('\0', '\x7f')],
+        "blank" => &[('\t', '\t'), (' ', ' ')],
+        "cntrl" => &[('\0', '\x1f'), ('\x7f', '\x7f')],
+        "digit" => &[('0', '9')],
+        "graph" => &[('!', '~')],
+        "lower" => &[('a', 'z')],
+        "print" => &[(' ', '~')],
+        "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+        "space" => &[('\t', '\r'), (' ', ' ')],
+        "upper" => &[('A', 'Z')],
+        "word" => &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+        "xdigit" => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+        _ => return None,
+    };
+    Some(ranges.to_vec())
+}
+
+#[derive(Clone, Copy)]
+enum Assertion {
+    // The flags are whether ^ and $ also match around line breaks.
+    LineStart(bool),
+    LineEnd(bool),
+    TextStart,
+    TextEnd,
+    // The flag is whether the boundary is negated (\B).
+    WordBoundary(bool),
+    WordStart,
+    WordEnd,
+    // \b{start-half} and \b{end-half}: only the side outside the word is
+    // checked, which is how -w wraps a pattern.
+    WordStartHalf,
+    WordEndHalf,
+}
+
+enum Node {
+    Empty,
+    // The flag is whether the comparison ignores case.
+    Char(char, bool),
+    // The flag is whether . also matches '\n'.
+    Any(bool),
+    Class(Class, bool),
+    Assert(Assertion),
+    Group(Box<Node>, usize),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+    BackRef(usize, bool),
+    // A lookbehind has the most characters its body can match.
+    Look {
+        node: Box<Node>,
+        behind: Option<usize>,
+        negate: bool,
+    },
+    Atomic(Box<Node>),
+}
+
+// The fewest characters a node can match, to tell whether a repetition of it
+// could go round without consuming anything.
+fn min_len(node: &Node) -> usize {
+    match node {
+        Node::Char(..) | Node::Any(_) | Node::Class(..) => 1,
+        Node::Empty | Node::Assert(_) | Node::Look { .. } | Node::BackRef(..) => 0,
+        Node::Group(inner, _) | Node::Atomic(inner) => min_len(inner),
+        Node::Concat(nodes) => nodes.iter().map(min_len).fold(0, usize::saturating_add),
+        Node::Alternate(alternatives) => alternatives.iter().map(min_len).min().unwrap_or(0),
+        Node::Repeat { node, min, .. } => min_len(node).saturating_mul(*min),
+    }
+}
+
+// The most characters a node can match, or None if there's no limit.
+fn max_len(node: &Node) -> Option<usize> {
+    match node {
+        Node::Char(..) | Node::Any(_) | Node::Class(..) => Some(1),
+        Node::Empty | Node::Assert(_) | Node::Look { .. } => Some(0),
+        Node::BackRef(..) => None,
+        Node::Group(inner, _) | Node::Atomic(inner) => max_len(inner),
+        Node::Concat(nodes) => nodes.iter().try_fold(0usize, |total, node| total.checked_add(max_len(node)?)),
+        Node::Alternate(alternatives) => alternatives.iter().try_fold(0, |most, node| Some(most.max(max_len(node)?))),
+        Node::Repeat { node, max, .. } => max_len(node)?.checked_mul((*max)?),
+    }
+}
+
+// Strings every match must contain, found without running the program, so
+// lines without one of them are rejected in linear time.
+fn required_literals(node: &Node, literals: &mut Vec<String>) {
+    match node {
+        Node::Char(c, false) => literals.push(c.to_string()),
+        Node::Group(inner, _) | Node::Atomic(inner) => required_literals(inner, literals),
+        Node::Repeat { node, min, .. } if *min > 0 => required_literals(node, literals),
+        Node::Concat(nodes) => {
+            let mut run = String::new();
+            for node in nodes {
+                if let Node::Char(c, false) = node {
+                    run.push(*c);
+                    continue;
+                }
+                if !run.is_empty() {
+                    literals.push(std::mem::take(&mut run));
+                }
+                required_literals(node, literals);
+            }
+            if !run.is_empty() {
+                literals.push(run);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_word(c: char, unicode: bool) -> bool {
+    if c.is_ascii() || !unicode {
+        c.is_ascii_alphanumeric() || c == '_'
+    } else {
+        c.is_alphanumeric() || c == '_'
+    }
+}
+
+fn chars_equal(a: char, b: char, case_insensitive: bool, unicode: bool) -> bool {
+    a == b || (case_insensitive && if unicode {
+        a.to_lowercase().eq(b.to_lowercase())
+    } else {
+        a.eq_ignore_ascii_case(&b)
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    names: Vec<Option<String>>,
+    // Backreferences are checked once the whole pattern is parsed, since they
+    // may refer to groups that are opened later.
+    max_backref: usize,
+    unicode: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let end = self.pos + s.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(s.chars()) {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.peek().ok_or("unexpected end of pattern")?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn parse_alternation(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        let mut alternatives = vec![self.parse_concat(flags)?];
+        while self.eat('|') {
+            alternatives.push(self.parse_concat(flags)?);
+        }
+        if alternatives.len() == 1 {
+            Ok(alternatives.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(alternatives))
+        }
+    }
+
+    fn parse_concat(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom(flags)?;
+            items.push(self.parse_quantifier(atom)?);
+        }
+        match items.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(items.pop().unwrap()),
+            _ => Ok(Node::Concat(items)),
+        }
+    }
+
+    fn parse_atom(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        match self.next()? {
+            '(' => self.parse_group(flags),
+            '[' => Ok(Node::Class(self.parse_class()?, flags.case_insensitive)),
+            '.' => Ok(Node::Any(flags.dot_all)),
+            '^' => Ok(Node::Assert(Assertion::LineStart(flags.multi_line))),
+            '$' => Ok(Node::Assert(Assertion::LineEnd(flags.multi_line))),
+            '\\' => self.parse_escape(flags),
+            '*' | '+' | '?' => Err("repetition operator missing expression".to_string()),
+            c => Ok(Node::Char(c, flags.case_insensitive)),
+        }
+    }
+
+    fn parse_name(&mut self, close: char) -> Result<String, String> {
+        let mut name = String::new();
+        loop {
+            match self.next()? {
+                c if c == close => break,
+                c if is_word(c, true) => name.push(c),
+                c => return Err(format!("invalid character {:?} in group name", c)),
+            }
+        }
+        if name.is_empty() {
+            return Err("empty group name".to_string());
+        }
+        Ok(name)
+    }
+
+    fn named_group(&self, name: &str) -> Result<usize, String> {
+        self.names.iter()
+            .position(|n| n.as_deref() == Some(name))
+            .ok_or_else(|| format!("unknown group name in backreference: {}", name))
+    }
+
+    fn parse_group(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        let mut inner_flags = *flags;
+        let node = if self.eat_str("?:") {
+            self.parse_alternation(&mut inner_flags)?
+        } else if self.eat_str("?=") || self.eat_str("?!") {
+            let negate = self.chars[self.pos - 1] == '!';
+            let node = Box::new(self.parse_alternation(&mut inner_flags)?);
+            Node::Look { node, behind: None, negate }
+        } else if self.eat_str("?<=") || self.eat_str("?<!") {
+            let negate = self.chars[self.pos - 1] == '!';
+            let node = Box::new(self.parse_alternation(&mut inner_flags)?);
+            // Trying every earlier starting point would make each attempt
+            // take time in proportion to the whole line.
+            let behind = max_len(&node).ok_or("lookbehind must have a bounded length")?;
+            Node::Look { node, behind: Some(behind), negate }
+        } else if self.eat_str("?>") {
+            Node::Atomic(Box::new(self.parse_alternation(&mut inner_flags)?))
+        } else if self.eat_str("?P=") {
+            let name = self.parse_name(')')?;
+            return Ok(Node::BackRef(self.named_group(&name)?, flags.case_insensitive));
+        } else if self.eat_str("?P<") || self.eat_str("?<") || self.eat_str("?'") {
+            let close = if self.chars[self.pos - 1] == '\'' { '\'' } else { '>' };
+            let name = self.parse_name(close)?;
+            let index = self.names.len();
+            self.names.push(Some(name));
+            Node::Group(Box::new(self.parse_alternation(&mut inner_flags)?), index)
+        } else if self.eat('?') {
+            // Inline flags: (?i) applies to the rest of the enclosing group and
+            // (?i:...) only inside its own.
+            let mut enable = true;
+            loop {
+                match self.next()? {
+                    'i' => inner_flags.case_insensitive = enable,
+                    'm' => inner_flags.multi_line = enable,
+                    's' => inner_flags.dot_all = enable,
+                    '-' => enable = false,
+                    ')' => {
+                        *flags = inner_flags;
+                        return Ok(Node::Empty);
+                    }
+                    ':' => break,
+                    c => return Err(format!("unrecognized flag {:?}", c)),
+                }
+            }
+            self.parse_alternation(&mut inner_flags)?
+        } else {
+            let index = self.names.len();
+            self.names.push(None);
+            Node::Group(Box::new(self.parse_alternation(&mut inner_flags)?), index)
+        };
+        if !self.eat(')') {
+            return Err("unclosed group".to_string());
+        }
+        Ok(node)
+    }
+
+    fn parse_hex(&mut self) -> Result<char, String> {
+        let digits: String = if self.eat('{') {
+            let mut digits = String::new();
+            while let Some(c) = self.peek() {
+                self.pos += 1;
+                if c == '}' {
+                    break;
+                }
+                digits.push(c);
+            }
+            digits
+        } else {
+            let digits = [self.next()?, self.next()?];
+            digits.iter().collect()
+        };
+        u32::from_str_radix(&digits, 16).ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("invalid hex escape: {}", digits))
+    }
+
+    // Escapes that stand for a single character, shared by classes and atoms.
+    fn simple_escape(&mut self, c: char) -> Result<Option<char>, String> {
+        Ok(match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            'f' => Some('\x0c'),
+            'v' => Some('\x0b'),
+            '0' => Some('\0'),
+            'x' => Some(self.parse_hex()?),
+            c if !c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+    }
+
+    fn perl_class(&self, c: char) -> Option<ClassItem> {
+        match c {
+            'd' | 'w' | 's' => Some(ClassItem::Set(perl_ranges(c, self.unicode), false)),
+            'D' | 'W' | 'S' => Some(ClassItem::Set(perl_ranges(c.to_ascii_lowercase(), self.unicode), true)),
+            _ => None,
+        }
+    }
+
+    fn parse_escape(&mut self, flags: &Flags) -> Result<Node, String> {
+        let c = self.next()?;
+        if let Some(item) = self.perl_class(c) {
+            let class = Class {
+                negated: false,
+                items: vec![item],
+            };
+            return Ok(Node::Class(class, flags.case_insensitive));
+        }
+        let assertion = match c {
+            'b' if self.eat_str("{start-half}") => Some(Assertion::WordStartHalf),
+            'b' if self.eat_str("{end-half}") => Some(Assertion::WordEndHalf),
+            'b' if self.eat_str("{start}") => Some(Assertion::WordStart),
+            'b' if self.eat_str("{end}") => Some(Assertion::WordEnd),
+            'b' => Some(Assertion::WordBoundary(false)),
+            'B' => Some(Assertion::WordBoundary(true)),
+            '<' => Some(Assertion::WordStart),
+            '>' => Some(Assertion::WordEnd),
+            'A' => Some(Assertion::TextStart),
+            'z' | 'Z' => Some(Assertion::TextEnd),
+            _ => None,
+        };
+        if let Some(assertion) = assertion {
+            return Ok(Node::Assert(assertion));
+        }
+        match c {
+            '1'..='9' => {
+                let mut index = c.to_digit(10).unwrap() as usize;
+                while let Some(digit) = self.peek().and_then(|d| d.to_digit(10)) {
+                    index = index * 10 + digit as usize;
+                    self.pos += 1;
+                }
+                self.max_backref = self.max_backref.max(index);
+                return Ok(Node::BackRef(index, flags.case_insensitive));
+            }
+            'k' => {
+                let close = match self.next()? {
+                    '<' => '>',
+                    '{' => '}',
+                    '\'' => '\'',
+                    _ => return Err("expected a group name after \\k".to_string()),
+                };
+                let name = self.parse_name(close)?;
+                return Ok(Node::BackRef(self.named_group(&name)?, flags.case_insensitive));
+            }
+            _ => {}
+        }
+        match self.simple_escape(c)? {
+            Some(c) => Ok(Node::Char(c, flags.case_insensitive)),
+            None => Err(format!("unsupported escape: \\{}", c)),
+        }
+    }
+
+    fn class_char(&mut self) -> Result<char, String> {
+        let c = self.next()?;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let escaped = self.next()?;
+        self.simple_escape(escaped)?
+            .ok_or_else(|| format!("unsupported escape in class: \\{}", escaped))
+    }
+
+    // Parses the rest of [:name:] or [:^name:] after the "[:".
+    fn parse_posix_class(&mut self) -> Result<ClassItem, String> {
+        let negated = self.eat('^');
+        let mut name = String::new();
+        while !self.eat_str(":]") {
+            name.push(self.next().map_err(|_| "unclosed POSIX class".to_string())?);
+        }
+        let ranges = posix_ranges(&name).ok_or_else(|| format!("invalid POSIX class name: {}", name))?;
+        Ok(ClassItem::Set(ranges, negated))
+    }
+
+    fn parse_class(&mut self) -> Result<Class, String> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            match self.peek() {
+                None => return Err("unclosed character class".to_string()),
+                Some(']') if !first => {
+                    self.pos += 1;
+                    break;
+                }
+                // As in the regex crate, a class inside a class adds to it.
+                Some('[') => {
+                    self.pos += 1;
+                    let item = if self.eat(':') {
+                        self.parse_posix_class()?
+                    } else {
+                        ClassItem::Nested(self.parse_class()?)
+                    };
+                    items.push(item);
+                    first = false;
+                    continue;
+                }
+                Some('\\') => {
+                    if let Some(item) = self.chars.get(self.pos + 1).and_then(|&c| self.perl_class(c)) {
+                        self.pos += 2;
+                        items.push(item);
+                        first = false;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            first = false;
+            let lo = self.class_char()?;
+            let is_range = self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']');
+            if is_range {
+                self.pos += 1;
+                let hi = self.class_char()?;
+                if hi < lo {
+                    return Err(format!("invalid class range {}-{}", lo, hi));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Class { negated, items })
+    }
+
+    fn parse_number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    // Parses {n}, {n,} or {n,m}. Anything else leaves the brace to be read as a
+    // literal, as PCRE does.
+    fn parse_counted(&mut self) -> Option<(usize, Option<usize>)> {
+        let start = self.pos;
+        let counted = (|| {
+            if !self.eat('{') {
+                return None;
+            }
+            let min = self.parse_number()?;
+            let max = if self.eat(',') {
+                if self.peek() == Some('}') { None } else { Some(self.parse_number()?) }
+            } else {
+                Some(min)
+            };
+            if !self.eat('}') {
+                return None;
+            }
+            Some((min, max))
+        })();
+        if counted.is_none() {
+            self.pos = start;
+        }
+        counted
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                (0, None)
+            }
+            Some('+') => {
+                self.pos += 1;
+                (1, None)
+            }
+            Some('?') => {
+                self.pos += 1;
+                (0, Some(1))
+            }
+            Some('{') => match self.parse_counted() {
+                Some(counted) => counted,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid repetition range {{{},{}}}", min, max.unwrap()));
+        }
+        let greedy = !self.eat('?');
+        let possessive = greedy && self.eat('+');
+        let repeat = Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        };
+        Ok(if possessive { Node::Atomic(Box::new(repeat)) } else { repeat })
+    }
+}
+
+// Counted repetitions are compiled as copies, so a pattern like a{1000}{1000}
+// is refused rather than compiled.
+const MAX_PROGRAM: usize = 1 << 20;
+
+#[derive(Clone)]
+enum Inst {
+    Char(char, bool),
+    Any(bool),
+    Class(Class, bool),
+    Assert(Assertion),
+    // Records the position in a slot: where a group starts or ends, or where
+    // an iteration of a repetition started.
+    Save(usize),
+    // Fails if the iteration that started at the position in the slot hasn't
+    // consumed anything, which keeps patterns like (a*)* from looping forever.
+    Progress(usize),
+    // Goes on at the first target, backtracking to the second.
+    Split(usize, usize),
+    Jump(usize),
+    BackRef(usize, bool),
+    // Runs the body that follows up to its End as a search of its own, then
+    // goes on at `next`.
+    Look {
+        behind: Option<usize>,
+        negate: bool,
+        next: usize,
+    },
+    Atomic {
+        next: usize,
+    },
+    End,
+}
+
+#[derive(Clone)]
+struct Program {
+    insts: Vec<Inst>,
+    // Two for each group, then one for each repetition that needs Progress.
+    slots: usize,
+    unicode: bool,
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+    slots: usize,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), String> {
+        if self.insts.len() > MAX_PROGRAM {
+            return Err("pattern is too big for the fancy engine".to_string());
+        }
+        match node {
+            Node::Empty => {}
+            Node::Char(c, case_insensitive) => {
+                self.push(Inst::Char(*c, *case_insensitive));
+            }
+            Node::Any(dot_all) => {
+                self.push(Inst::Any(*dot_all));
+            }
+            Node::Class(class, case_insensitive) => {
+                self.push(Inst::Class(class.clone(), *case_insensitive));
+            }
+            Node::Assert(assertion) => {
+                self.push(Inst::Assert(*assertion));
+            }
+            Node::Group(inner, index) => {
+                self.push(Inst::Save(2 * index));
+                self.compile(inner)?;
+                self.push(Inst::Save(2 * index + 1));
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alternate(alternatives) => {
+                let mut jumps = Vec::new();
+                let (last, rest) = alternatives.split_last().expect("an alternation has alternatives");
+                for alternative in rest {
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile(alternative)?;
+                    jumps.push(self.push(Inst::Jump(0)));
+                    self.insts[split] = Inst::Split(split + 1, self.insts.len());
+                }
+                self.compile(last)?;
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jump(self.insts.len());
+                }
+            }
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                let mark = if min_len(node) == 0 {
+                    self.slots += 1;
+                    Some(self.slots - 1)
+                } else {
+                    None
+                };
+                let mut splits = Vec::new();
+                match max {
+                    None => {
+                        let split = self.push(Inst::Split(0, 0));
+                        self.iteration(node, mark)?;
+                        self.push(Inst::Jump(split));
+                        splits.push(split);
+                    }
+                    Some(max) => {
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0)));
+                            self.iteration(node, mark)?;
+                        }
+                    }
+                }
+                let end = self.insts.len();
+                for split in splits {
+                    self.insts[split] = if *greedy { Inst::Split(split + 1, end) } else { Inst::Split(end, split + 1) };
+                }
+            }
+            Node::BackRef(index, case_insensitive) => {
+                self.push(Inst::BackRef(*index, *case_insensitive));
+            }
+            Node::Look { node, behind, negate } => {
+                let look = self.push(Inst::End);
+                self.compile(node)?;
+                self.push(Inst::End);
+                self.insts[look] = Inst::Look { behind: *behind, negate: *negate, next: self.insts.len() };
+            }
+            Node::Atomic(inner) => {
+                let atomic = self.push(Inst::End);
+                self.compile(inner)?;
+                self.push(Inst::End);
+                self.insts[atomic] = Inst::Atomic { next: self.insts.len() };
+            }
+        }
+        Ok(())
+    }
+
+    // One optional iteration of a repetition.
+    fn iteration(&mut self, node: &Node, mark: Option<usize>) -> Result<(), String> {
+        if let Some(slot) = mark {
+            self.push(Inst::Save(slot));
+        }
+        self.compile(node)?;
+        if let Some(slot) = mark {
+            self.push(Inst::Progress(slot));
+        }
+        Ok(())
+    }
+}
+
+type Groups = Vec<Option<(usize, usize)>>;
+
+enum Frame {
+    // Where to go on from if what follows fails.
+    Retry(usize, usize),
+    // A slot's value before it was changed.
+    Restore(usize, Option<usize>),
+}
+
+fn char_at(text: &str, pos: usize) -> Option<char> {
+    text.get(pos..).and_then(|rest| rest.chars().next())
+}
+
+fn char_before(text: &str, pos: usize) -> Option<char> {
+    text[..pos].chars().next_back()
+}
+
+struct Search<'a> {
+    program: &'a Program,
+    text: &'a str,
+    slots: Vec<Option<usize>>,
+    stack: Vec<Frame>,
+}
+
+impl Search<'_> {
+    fn set(&mut self, slot: usize, value: Option<usize>) {
+        let old = std::mem::replace(&mut self.slots[slot], value);
+        self.stack.push(Frame::Restore(slot, old));
+    }
+
+    fn is_word_at(&self, c: Option<char>) -> bool {
+        c.is_some_and(|c| is_word(c, self.program.unicode))
+    }
+
+    fn assert(&self, assertion: Assertion, pos: usize) -> bool {
+        let text = self.text;
+        let bytes = text.as_bytes();
+        let before = || self.is_word_at(char_before(text, pos));
+        let after = || self.is_word_at(char_at(text, pos));
+        match assertion {
+            Assertion::LineStart(multi_line) => pos == 0 || (multi_line && bytes[pos - 1] == b'\n'),
+            Assertion::LineEnd(multi_line) => {
+                pos == text.len()
+                    || (multi_line && (bytes[pos] == b'\n' || bytes[pos..].starts_with(b"\r\n")))
+            }
+            Assertion::TextStart => pos == 0,
+            Assertion::TextEnd => pos == text.len(),
+            Assertion::WordBoundary(negated) => (before() != after()) != negated,
+            Assertion::WordStart => !before() && after(),
+            Assertion::WordEnd => before() && !after(),
+            Assertion::WordStartHalf => !before(),
+            Assertion::WordEndHalf => !after(),
+        }
+    }
+
+    // Runs the program from `pc` at `pos` until an End, which must be at
+    // `end` if that's given, and returns where it matched up to. On failure
+    // the slots are as they were.
+    fn run(&mut self, mut pc: usize, mut pos: usize, end: Option<usize>) -> Option<usize> {
+        let program = self.program;
+        let text = self.text;
+        let unicode = program.unicode;
+        let base = self.stack.len();
+        loop {
+            let matched = match &program.insts[pc] {
+                Inst::Char(c, case_insensitive) => match char_at(text, pos) {
+                    Some(d) if chars_equal(*c, d, *case_insensitive, unicode) => {
+                        pos += d.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Any(dot_all) => match char_at(text, pos) {
+                    Some(d) if *dot_all || d != '\n' => {
+                        pos += d.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Class(class, case_insensitive) => match char_at(text, pos) {
+                    Some(d) if class.contains(d, *case_insensitive, unicode) => {
+                        pos += d.len_utf8();
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Assert(assertion) => self.assert(*assertion, pos),
+                Inst::Save(slot) => {
+                    self.set(*slot, Some(pos));
+                    true
+                }
+                Inst::Progress(slot) => self.slots[*slot] != Some(pos),
+                Inst::Split(first, second) => {
+                    self.stack.push(Frame::Retry(*second, pos));
+                    pc = *first;
+                    continue;
+                }
+                Inst::Jump(target) => {
+                    pc = *target;
+                    continue;
+                }
+                Inst::BackRef(index, case_insensitive) => match (self.slots[2 * index], self.slots[2 * index + 1]) {
+                    (Some(start), Some(group_end)) => {
+                        let mut rest = text[pos..].chars();
+                        let mut consumed = 0;
+                        let equal = text[start..group_end].chars().all(|c| match rest.next() {
+                            Some(d) if chars_equal(c, d, *case_insensitive, unicode) => {
+                                consumed += d.len_utf8();
+                                true
+                            }
+                            _ => false,
+                        });
+                        pos += consumed;
+                        equal
+                    }
+                    _ => false,
+                },
+                Inst::Look { behind, negate, next } => {
+                    let found = self.look(pc + 1, pos, *behind, *negate);
+                    pc = *next;
+                    if found {
+                        continue;
+                    }
+                    false
+                }
+                Inst::Atomic { next } => match self.nested(pc + 1, pos, None) {
+                    Some(found) => {
+                        pos = found;
+                        pc = *next;
+                        continue;
+                    }
+                    None => false,
+                },
+                Inst::End => {
+                    if end.is_none_or(|end| end == pos) {
+                        self.stack.truncate(base);
+                        return Some(pos);
+                    }
+                    false
+                }
+            };
+            if matched {
+                pc += 1;
+                continue;
+            }
+            loop {
+                if self.stack.len() == base {
+                    return None;
+                }
+                match self.stack.pop() {
+                    Some(Frame::Retry(retry_pc, retry_pos)) => {
+                        pc = retry_pc;
+                        pos = retry_pos;
+                        break;
+                    }
+                    Some(Frame::Restore(slot, value)) => self.slots[slot] = value,
+                    None => return None,
+                }
+            }
+        }
+    }
+
+    // Runs the body of an atomic group or positive lookaround, taking its
+    // first match only. The groups it captured are kept, and put back as
+    // they were if what follows fails.
+    fn nested(&mut self, pc: usize, pos: usize, end: Option<usize>) -> Option<usize> {
+        let saved = self.slots.clone();
+        let found = self.run(pc, pos, end)?;
+        for (slot, old) in saved.into_iter().enumerate() {
+            if self.slots[slot] != old {
+                self.stack.push(Frame::Restore(slot, old));
+            }
+        }
+        Some(found)
+    }
+
+    fn look(&mut self, body: usize, pos: usize, behind: Option<usize>, negate: bool) -> bool {
+        let saved = if negate { Some((self.slots.clone(), self.stack.len())) } else { None };
+        let found = match behind {
+            None => self.nested(body, pos, None).is_some(),
+            // A lookbehind is tried starting at each of the characters its
+            // body can span before `pos`, nearest first.
+            Some(max) => {
+                let mut start = pos;
+                let mut found = false;
+                for _ in 0..=max {
+                    if self.nested(body, start, Some(pos)).is_some() {
+                        found = true;
+                        break;
+                    }
+                    match char_before(self.text, start) {
+                        Some(c) => start -= c.len_utf8(),
+                        None => break,
+                    }
+                }
+                found
+            }
+        };
+        // Nothing a negative lookaround captured is kept.
+        if let Some((slots, base)) = saved {
+            if found {
+                self.slots = slots;
+                self.stack.truncate(base);
+            }
+            return !found;
+        }
+        found
+    }
+}
+
+#[derive(Clone)]
+pub struct Regex {
+    program: Program,
+    required: Vec<String>,
+    names: Vec<Option<String>>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str, flags: &Flags) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            names: vec![None],
+            max_backref: 0,
+            unicode: !flags.no_unicode,
+        };
+        let mut flags = *flags;
+        let root = parser.parse_alternation(&mut flags)?;
+        if parser.pos < parser.chars.len() {
+            return Err("unopened group".to_string());
+        }
+        if parser.max_backref >= parser.names.len() {
+            return Err(format!("invalid backreference \\{}", parser.max_backref));
+        }
+        let mut compiler = Compiler {
+            insts: Vec::new(),
+            slots: 2 * parser.names.len(),
+        };
+        compiler.compile(&root)?;
+        compiler.push(Inst::End);
+        let mut required = Vec::new();
+        required_literals(&root, &mut required);
+        Ok(Regex {
+            required,
+            program: Program {
+                insts: compiler.insts,
+                slots: compiler.slots,
+                unicode: parser.unicode,
+            },
+            names: parser.names,
+        })
+    }
+
+    // Group names indexed by group number; group 0 is the whole match.
+    pub fn names(&self) -> &[Option<String>] {
+        &self.names
+    }
+
+    // Finds the leftmost match starting at or after `start` and returns the span
+    // of every group, with group 0 covering the whole match.
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Groups> {
+        if self.required.iter().any(|required| !text[start..].contains(required.as_str())) {
+            return None;
+        }
+        let mut search = Search {
+            program: &self.program,
+            text,
+            slots: vec![None; self.program.slots],
+            stack: Vec::new(),
+        };
+        for pos in start..=text.len() {
+            if !text.is_char_boundary(pos) {
+                continue;
+            }
+            if let Some(end) = search.run(0, pos, None) {
+                let mut groups: Groups = (0..self.names.len())
+                    .map(|index| Some((search.slots[2 * index]?, search.slots[2 * index + 1]?)))
+                    .collect();
+                groups[0] = Some((pos, end));
+                return Some(groups);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Flags, Regex};
+
+    fn find(pattern: &str, text: &str) -> Option<String> {
+        find_with(pattern, text, Flags::default())
+    }
+
+    fn find_with(pattern: &str, text: &str, flags: Flags) -> Option<String> {
+        let re = Regex::new(pattern, &flags).unwrap();
+        let (start, end) = re.captures_at(text, 0)?[0]?;
+        Some(text[start..end].to_string())
+    }
+
+    fn groups(pattern: &str, text: &str) -> Vec<Option<String>> {
+        let re = Regex::new(pattern, &Flags::default()).unwrap();
+        re.captures_at(text, 0).unwrap().into_iter()
+            .map(|group| group.map(|(start, end)| text[start..end].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn backreferences() {
+        assert_eq!(find(r"(\w+) \1", "say hello hello"), Some("hello hello".to_string()));
+        assert_eq!(find(r"(?P<word>\w+) (?P=word)", "a bb bb"), Some("bb bb".to_string()));
+        assert_eq!(find(r"(?<q>['\x22]).*?\k<q>", "x 'y' z"), Some("'y'".to_string()));
+        assert_eq!(find(r"(a)\1", "ab"), None);
+        assert!(Regex::new(r"(a)\2", &Flags::default()).is_err());
+        let flags = Flags { case_insensitive: true, ..Flags::default() };
+        assert_eq!(find_with(r"(ab)\1", "abAB", flags), Some("abAB".to_string()));
+    }
+
+    #[test]
+    fn lookaround() {
+        assert_eq!(find(r"foo(?=bar)", "foobaz foobar"), Some("foo".to_string()));
+        assert_eq!(find(r"foo(?!bar)\w", "foobar foobaz"), Some("foob".to_string()));
+        assert_eq!(find(r"(?<=\$)\d+", "cost 3, price $42"), Some("42".to_string()));
+        assert_eq!(find(r"(?<!\$)\b\d+", "$42 and 7"), Some("7".to_string()));
+        assert_eq!(find(r"(?<=ab|c)d", "abd"), Some("d".to_string()));
+        assert!(Regex::new(r"(?<=a+)b", &Flags::default()).is_err());
+        // Groups captured in a positive lookahead are kept, those in a
+        // negative one aren't.
+        assert_eq!(groups(r"(?=(a))a", "a"), vec![Some("a".to_string()), Some("a".to_string())]);
+        assert_eq!(groups(r"(?!(b))a", "a"), vec![Some("a".to_string()), None]);
+    }
+
+    #[test]
+    fn atomic_groups_and_possessive_quantifiers() {
+        assert_eq!(find(r"(?>a+)b", "aaab"), Some("aaab".to_string()));
+        assert_eq!(find(r"(?>a+)a", "aaaa"), None);
+        assert_eq!(find(r"a++a", "aaaa"), None);
+        assert_eq!(find(r"a*+b", "aab"), Some("aab".to_string()));
+        assert_eq!(find(r"(?>ab|a)b", "ab"), None);
+    }
+
+    #[test]
+    fn repetition() {
+        assert_eq!(find(r"a{2,3}", "aaaa"), Some("aaa".to_string()));
+        assert_eq!(find(r"a{2,3}?", "aaaa"), Some("aa".to_string()));
+        assert_eq!(find(r"a{2}", "a"), None);
+        assert_eq!(find(r"x{,2}", "x{,2}"), Some("x{,2}".to_string()));
+        assert_eq!(find(r"(a*)*b", "aab"), Some("aab".to_string()));
+        assert_eq!(find(r"(a|)+c", "aac"), Some("aac".to_string()));
+        assert!(Regex::new(r"(?:(?:a{1000}){1000}){1000}", &Flags::default()).is_err());
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(find(r"[[:alpha:]]+", "12ab3"), Some("ab".to_string()));
+        assert_eq!(find(r"[[:^digit:][:space:]]+", "12a b3"), Some("a b".to_string()));
+        assert!(Regex::new(r"[[:bogus:]]", &Flags::default()).is_err());
+        assert_eq!(find(r"[a-c[x-z]]+", "mbyan"), Some("bya".to_string()));
+        assert_eq!(find(r"[^\d\s]+", "1 ab 2"), Some("ab".to_string()));
+        assert_eq!(find(r"[]a]+", "x]a]"), Some("]a]".to_string()));
+        assert_eq!(find(r"\d+", "x١٢٣"), Some("١٢٣".to_string()));
+        assert_eq!(find(r"\w+", "café!"), Some("café".to_string()));
+        let ascii = Flags { no_unicode: true, ..Flags::default() };
+        assert_eq!(find_with(r"\d", "x١٢٣", ascii), None);
+        assert_eq!(find_with(r"\w+", "café!", ascii), Some("caf".to_string()));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_eq!(find(r"\bbar\b", "foobar bar"), Some("bar".to_string()));
+        assert_eq!(find(r"\Bbar", "bar foobar"), Some("bar".to_string()));
+        assert_eq!(find(r"\<bar\>", "rebar bar"), Some("bar".to_string()));
+        assert_eq!(find(r"\b{start}b\b{end}", "ab b"), Some("b".to_string()));
+        // How -w wraps a pattern.
+        assert_eq!(find(r"\b{start-half}(?:-x)\b{end-half}", "a-x -x"), Some("-x".to_string()));
+        assert_eq!(find(r"\b{start-half}(?:bar)\b{end-half}", "foobar bars"), None);
+        assert_eq!(find(r"\bé", "café é"), Some("é".to_string()));
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(find(r"(?i)HELLO", "say hello"), Some("hello".to_string()));
+        assert_eq!(find(r"a(?i:B)c", "abc aBC"), Some("abc".to_string()));
+        assert_eq!(find(r"(?i)straße", "STRASSE Straße"), Some("Straße".to_string()));
+        assert_eq!(find(r"a.b", "a\nb"), None);
+        assert_eq!(find(r"(?s)a.b", "a\nb"), Some("a\nb".to_string()));
+        assert_eq!(find(r"^b$", "a\nb"), None);
+        assert_eq!(find(r"(?m)^b$", "a\nb\nc"), Some("b".to_string()));
+        let flags = Flags { multi_line: true, ..Flags::default() };
+        assert_eq!(find_with(r"a$", "a\r\nb", flags), Some("a".to_string()));
+        let ascii = Flags { case_insensitive: true, no_unicode: true, ..Flags::default() };
+        assert_eq!(find_with(r"É", "é", ascii), None);
+        assert_eq!(find_with(r"E", "e", ascii), Some("e".to_string()));
+    }
+
+    #[test]
+    fn long_lines() {
+        let long = "a".repeat(200_000);
+        assert_eq!(find(r"^.*$", &long).map(|m| m.len()), Some(long.len()));
+        assert_eq!(find(r"a*b", &long), None);
+        assert_eq!(find(r"(a|b)*c", &long), None);
+        assert_eq!(find(r"(?:a|b)*$", &long).map(|m| m.len()), Some(long.len()));
+        assert_eq!(find(r"(a*)*$", &long).map(|m| m.len()), Some(long.len()));
+        assert_eq!(find(r"(?<=a)a*$", &long).map(|m| m.len()), Some(long.len() - 1));
+        let with_end = format!("{}b", long);
+        assert_eq!(find(r"a*b", &with_end).map(|m| m.len()), Some(with_end.len()));
+        assert_eq!(find(r"(?>a*)b", &with_end).map(|m| m.len()), Some(with_end.len()));
+        assert_eq!(find(r"\w+\b", &with_end).map(|m| m.len()), Some(with_end.len()));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::matcher::Captures;
+
+enum Piece {
+    Literal(String),
+    Path,
+    Line,
+    Column,
+    Text,
+    Match,
+    Group(usize),
+}
+
+// A template for --format. Placeholders are {path}, {line}, {column}, {text},
+// {match} and {N} for capture group N; {{ and }} stand for literal braces, and
+// \t, \n and \\ are unescaped so tab-separated output can be written from a
+// shell without $'...' quoting.
+pub struct OutputFormat {
+    pieces: Vec<Piece>,
+}
+
+impl OutputFormat {
+    pub fn parse(template: &str) -> Result<OutputFormat, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        literal.push('\\');
+                        literal.push(other);
+                    }
+                    None => literal.push('\\'),
+                },
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unterminated placeholder in format: {}", template)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(placeholder(&name)?);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(OutputFormat { pieces })
+    }
+
+    // `captures` is None for lines selected by -v, which have no match; their
+    // match, column and group placeholders expand to nothing.
+    pub fn render(&self, path: &str, line_number: usize, text: &str, captures: Option<&Captures>) -> String {
+        let group = |n: usize| captures.and_then(|c| c.get(n));
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(s) => out.push_str(s),
+                Piece::Path => out.push_str(path),
+                Piece::Line => out.push_str(&line_number.to_string()),
+                Piece::Column => {
+                    if let Some(m) = group(0) {
+                        out.push_str(&(m.start() + 1).to_string());
+                    }
+                }
+                Piece::Text => out.push_str(text),
+                Piece::Match => out.push_str(group(0).map_or("", |m| m.as_str())),
+                Piece::Group(n) => out.push_str(group(*n).map_or("", |m| m.as_str())),
+            }
+        }
+        out
+    }
+}
+
+fn placeholder(name: &str) -> Result<Piece, String> {
+    match name {
+        "path" => Ok(Piece::Path),
+        "line" => Ok(Piece::Line),
+        "column" => Ok(Piece::Column),
+        "text" => Ok(Piece::Text),
+        "match" => Ok(Piece::Match),
+        _ => name.parse().map(Piece::Group)
+            .map_err(|_| format!("unknown placeholder {{{}}} in format", name)),
+    }
+}
@@ -0,0 +1,199 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::color::Style;
+use crate::matcher::Matcher;
+use crate::process;
+use crate::stats::Stats;
+use crate::walk;
+use crate::{highlight_matches, is_selected, match_line, print_record, search_member, styled, warn, MinigrepOptions};
+
+// Runs git with `args` in `dir` and returns its output, or what it printed to
+// stderr if it failed.
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("couldn't run git: {}", e))?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(message.trim().trim_start_matches("fatal: ").to_string());
+    }
+    Ok(output.stdout)
+}
+
+// The files under `dir` that are in the index of the repository it belongs
+// to, in git's order.
+pub fn tracked_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let output = git(dir, &["ls-files", "-z", "--cached"])?;
+    Ok(output.split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| dir.join(String::from_utf8_lossy(name).as_ref()))
+        .collect())
+}
+
+// The user's global ignore file: core.excludesFile if it's set, otherwise
+// git's default of $XDG_CONFIG_HOME/git/ignore.
+pub fn excludes_file() -> Option<PathBuf> {
+    if let Ok(output) = git(Path::new("."), &["config", "--path", "--get", "core.excludesFile"]) {
+        let path = String::from_utf8_lossy(&output).trim().to_string();
+        if !path.is_empty() {
+            return Some(PathBuf::from(path));
+        }
+    }
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("git/ignore"))
+}
+
+// Searches every file of revision `rev` under `paths`, which are relative to
+// the current directory as the work tree's would be. Each is named rev:path
+// in the output, as with git grep.
+pub fn search_revision(options: &MinigrepOptions, matcher: &Matcher, rev: &str, paths: &[String]) -> Stats {
+    let mut total = Stats::default();
+    if let Err(e) = search_tree(options, matcher, rev, paths, &mut total) {
+        warn(options, &format!("couldn't search {}: {}", rev, e));
+    }
+    total
+}
+
+fn search_tree(options: &MinigrepOptions, matcher: &Matcher, rev: &str, paths: &[String], total: &mut Stats) -> Result<(), String> {
+    let mut args = vec!["ls-tree", "-r", "-z", rev, "--"];
+    args.extend(paths.iter().map(String::as_str));
+    let listing = git(Path::new("."), &args)?;
+    // Entries are "MODE TYPE OBJECT\tPATH"; symlinks and submodules are
+    // skipped, as they are in the work tree.
+    let blobs: Vec<(&str, String)> = listing.split(|&b| b == 0)
+        .filter_map(|entry| {
+            let entry = std::str::from_utf8(entry).ok()?;
+            let (info, path) = entry.split_once('\t')?;
+            let mut fields = info.split(' ');
+            let (mode, kind, object) = (fields.next()?, fields.next()?, fields.next()?);
+            let wanted = kind == "blob" && mode != "120000" && walk::file_wanted(options, Path::new(path))
+                && !walk::in_excluded_dir(options, Path::new(""), Path::new(path));
+            wanted.then(|| (object, path.to_string()))
+        })
+        .collect();
+
+    // One cat-file process serves every blob: it's sent an object name and
+    // answers with "OBJECT blob SIZE\n", the contents and a newline.
+    let mut cat_file = Command::new("git")
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't run git: {}", e))?;
+    let mut requests = cat_file.stdin.take().expect("stdin is piped");
+    let mut responses = BufReader::new(cat_file.stdout.take().expect("stdout is piped"));
+    let result = (|| {
+        for (object, path) in blobs {
+            writeln!(requests, "{}", object).and_then(|_| requests.flush()).map_err(|e| e.to_string())?;
+            let mut header = String::new();
+            responses.read_line(&mut header).map_err(|e| e.to_string())?;
+            let size: u64 = header.split(' ').nth(2)
+                .and_then(|size| size.trim().parse().ok())
+                .ok_or_else(|| format!("unexpected reply from git cat-file: {}", header.trim()))?;
+            let mut blob = (&mut responses).take(size);
+            let name = format!("{}:{}", rev, path);
+            let stop = search_member(options, matcher, &name, Box::new(&mut blob), total);
+            io::copy(&mut blob, &mut io::sink()).map_err(|e| e.to_string())?;
+            responses.read_line(&mut String::new()).map_err(|e| e.to_string())?;
+            if stop {
+                break;
+            }
+        }
+        Ok(())
+    })();
+    drop(requests);
+    let _ = cat_file.wait();
+    result
+}
+
+// Each commit in the `git log` output starts with a line holding this marker
+// and the fields of HISTORY_FORMAT, which are separated by tabs.
+const COMMIT_MARKER: &str = "\0commit ";
+const HISTORY_FORMAT: &str = "--format=%x00commit %h%x09%ad%x09%an%x09%s";
+
+// Walks the history of `paths`, newest first, for `minigrep history`. Like
+// git log -G, a commit is reported when its diff adds or removes a line
+// matching the patterns; it's printed as "HASH DATE AUTHOR: SUBJECT" and
+// followed by those lines, each with its file and a + or -.
+pub fn search_history(options: &MinigrepOptions, matcher: &Matcher, paths: &[String]) -> Stats {
+    let mut log = Command::new("git");
+    log.args(["log", "-p", "--no-color", "--no-ext-diff", "--date=short", HISTORY_FORMAT, "--"])
+        .args(paths)
+        .stdin(Stdio::null());
+    let reader = match process::spawn_reader(log, "git") {
+        Ok(reader) => reader,
+        Err(e) => {
+            warn(options, &format!("couldn't read the history: {}", e));
+            return Stats::default();
+        }
+    };
+
+    let mut total = Stats::default();
+    let mut commit = String::new();
+    let mut commit_printed = false;
+    let mut path = String::new();
+    let mut in_file_header = false;
+    for line in reader.split(b'\n') {
+        let line = match line {
+            Ok(line) => String::from_utf8_lossy(&line).into_owned(),
+            Err(e) => {
+                warn(options, &format!("error reading the history: {}", e));
+                break;
+            }
+        };
+        if let Some(fields) = line.strip_prefix(COMMIT_MARKER) {
+            let fields: Vec<&str> = fields.splitn(4, '\t').collect();
+            if let [hash, date, author, subject] = fields.as_slice() {
+                commit = format!("{} {} {}: {}", styled(options, Style::Path, hash), date, author, subject);
+            }
+            commit_printed = false;
+            total.searches += 1;
+            continue;
+        }
+        // The ---/+++ lines name the file only in the header before a diff's
+        // first hunk; later they're removed or added lines.
+        if line.starts_with("diff ") {
+            in_file_header = true;
+            continue;
+        }
+        if in_file_header {
+            if line.starts_with("@@") {
+                in_file_header = false;
+            } else if let Some(name) = line.strip_prefix("+++ b/").or_else(|| line.strip_prefix("--- a/")) {
+                path = name.to_string();
+            }
+            continue;
+        }
+        let (sign, text) = match line.chars().next() {
+            Some(sign @ ('+' | '-')) => (sign, &line[1..]),
+            _ => continue,
+        };
+        if !is_selected(options, match_line(options, matcher, text)) {
+            continue;
+        }
+        total.matched_lines += 1;
+        if options.quiet {
+            break;
+        }
+        if !commit_printed {
+            print_record(options, &commit);
+            total.searches_with_match += 1;
+            commit_printed = true;
+        }
+        if !options.files_with_matches {
+            let location = styled(options, Style::Path, &path);
+            print_record(options, &format!("    {}:{}{}", location, sign, highlight_matches(options, matcher, text)));
+        }
+    }
+    total
+}
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use regex::Regex;
+
+// A shell-style file name pattern: * matches any run of characters other
+// than /, ** any run including /, ? one character, [abc] and [!abc] a class
+// and {a,b} either alternative. A glob without a / is matched against the
+// file name alone, so *.rs finds Rust files anywhere; one with a / is
+// matched against the whole path.
+pub struct Glob {
+    regex: Regex,
+    whole_path: bool,
+}
+
+// Translates a glob to an anchored regex with the same meaning.
+pub fn to_regex(pattern: &str) -> Result<String, String> {
+    let invalid = |reason: &str| format!("invalid glob {}: {}", pattern, reason);
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    let mut in_alternation = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // **/ also matches no directories at all.
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if matches!(chars.peek(), Some('!') | Some('^')) {
+                    chars.next();
+                    regex.push('^');
+                }
+                let mut closed = false;
+                let mut first = true;
+                for c in chars.by_ref() {
+                    match c {
+                        ']' if !first => {
+                            closed = true;
+                            break;
+                        }
+                        '-' if !first => regex.push('-'),
+                        c => regex.push_str(&regex::escape(&c.to_string())),
+                    }
+                    first = false;
+                }
+                if !closed {
+                    return Err(invalid("unclosed ["));
+                }
+                regex.push(']');
+            }
+            '{' if !in_alternation => {
+                in_alternation = true;
+                regex.push_str("(?:");
+            }
+            ',' if in_alternation => regex.push('|'),
+            '}' if in_alternation => {
+                in_alternation = false;
+                regex.push(')');
+            }
+            '\\' => match chars.next() {
+                Some(escaped) => regex.push_str(&regex::escape(&escaped.to_string())),
+                None => return Err(invalid("trailing \\")),
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if in_alternation {
+        return Err(invalid("unclosed {"));
+    }
+    regex.push('$');
+    Ok(regex)
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Glob, String> {
+        let regex = to_regex(pattern)?;
+        Ok(Glob {
+            regex: Regex::new(&regex).map_err(|e| format!("invalid glob {}: {}", pattern, e))?,
+            whole_path: pattern.contains('/'),
+        })
+    }
+
+    pub fn is_match(&self, path: &Path) -> bool {
+        let text = if self.whole_path {
+            let path = path.to_string_lossy();
+            path.strip_prefix("./").unwrap_or(&path).to_string()
+        } else {
+            path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+        };
+        self.regex.is_match(&text)
+    }
+}
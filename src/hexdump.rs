@@ -0,0 +1,43 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+const BYTES_PER_ROW: usize = 16;
+// The width of a full row's hex column: two digits per byte and a space
+// between each pair of bytes.
+const ROW_WIDTH: usize = BYTES_PER_ROW * 2 + BYTES_PER_ROW / 2 - 1;
+
+// Bytes shown on either side of a match.
+pub const CONTEXT_BYTES: usize = 16;
+
+// Formats `bytes`, which start at `offset` in the file, as rows in the style
+// of `xxd`: the offset of the row, the bytes in groups of two and the bytes
+// as ASCII with '.' for anything unprintable. Bytes in `highlight` (indexes
+// into `bytes`) are passed through `paint` in both columns.
+pub fn rows(bytes: &[u8], offset: usize, highlight: Range<usize>, paint: impl Fn(&str) -> String) -> Vec<String> {
+    bytes.chunks(BYTES_PER_ROW)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let row_start = row * BYTES_PER_ROW;
+            let mut hex = String::new();
+            let mut ascii = String::new();
+            for (i, &byte) in chunk.iter().enumerate() {
+                if i > 0 && i % 2 == 0 {
+                    hex.push(' ');
+                }
+                let printable = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+                if highlight.contains(&(row_start + i)) {
+                    hex.push_str(&paint(&format!("{:02x}", byte)));
+                    ascii.push_str(&paint(&printable.to_string()));
+                } else {
+                    let _ = write!(hex, "{:02x}", byte);
+                    ascii.push(printable);
+                }
+            }
+            // Pad short rows so the ASCII column lines up; the width doesn't
+            // count the escape codes of highlighted bytes.
+            let width = chunk.len() * 2 + (chunk.len() - 1) / 2;
+            hex.push_str(&" ".repeat(ROW_WIDTH - width));
+            format!("{:08x}: {}  {}", offset + row_start, hex, ascii)
+        })
+        .collect()
+}
@@ -0,0 +1,92 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// A URL template for OSC 8 terminal hyperlinks around printed paths. The
+// placeholders {path}, {line}, {column} and {host} are filled in per link.
+pub struct HyperlinkFormat {
+    template: String,
+    host: String,
+}
+
+const PLACEHOLDERS: &[&str] = &["{path}", "{line}", "{column}", "{host}"];
+
+impl HyperlinkFormat {
+    pub fn parse(value: &str) -> Result<Option<HyperlinkFormat>, String> {
+        let template = match value {
+            "none" | "" => return Ok(None),
+            "default" | "file" => "file://{host}{path}",
+            "vscode" => "vscode://file{path}:{line}:{column}",
+            "cursor" => "cursor://file{path}:{line}:{column}",
+            "kitty" => "file://{host}{path}#{line}",
+            "macvim" => "mvim://open?url=file://{path}&line={line}&column={column}",
+            "textmate" => "txmt://open?url=file://{path}&line={line}&column={column}",
+            "grep+" => "grep+://{path}:{line}",
+            template => template,
+        };
+        if !template.contains("{path}") {
+            return Err(format!("hyperlink format must contain {{path}}: {}", value));
+        }
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}')
+                .ok_or_else(|| format!("unterminated placeholder in hyperlink format: {}", value))?;
+            let placeholder = &rest[start..start + end + 1];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!("unknown placeholder {} in hyperlink format", placeholder));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(Some(HyperlinkFormat {
+            template: template.to_string(),
+            host: hostname(),
+        }))
+    }
+
+    pub fn uses_line(&self) -> bool {
+        self.template.contains("{line}")
+    }
+
+    // Lines and columns default to 1 for links that aren't tied to a line, such
+    // as -l output.
+    fn url(&self, path: &str, line: Option<usize>, column: Option<usize>) -> String {
+        let absolute = fs::canonicalize(Path::new(path))
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+        let mut encoded = percent_encode(&absolute.replace('\\', "/"));
+        if !encoded.starts_with('/') {
+            encoded.insert(0, '/');
+        }
+        self.template
+            .replace("{host}", &self.host)
+            .replace("{line}", &line.unwrap_or(1).to_string())
+            .replace("{column}", &column.unwrap_or(1).to_string())
+            .replace("{path}", &encoded)
+    }
+
+    pub fn wrap(&self, text: &str, path: &str, line: Option<usize>, column: Option<usize>) -> String {
+        format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", self.url(path, line, column), text)
+    }
+}
+
+fn hostname() -> String {
+    if let Ok(host) = env::var("HOSTNAME") {
+        return host;
+    }
+    fs::read_to_string("/etc/hostname")
+        .map(|host| host.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
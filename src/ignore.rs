@@ -0,0 +1,127 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::glob;
+
+// One line of a gitignore file. A pattern with a / before its end is
+// anchored to the directory of the file it's in; one without matches a name
+// at any depth. A trailing / limits it to directories and a leading !
+// re-includes what an earlier line excluded.
+struct Rule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+// The rules of one ignore file, which apply to paths under `base`.
+pub struct IgnoreRules {
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    // Trailing spaces are dropped unless escaped with a backslash.
+    let mut pattern = line.trim_end_matches([' ', '\t']).to_string();
+    if line.len() > pattern.len() && pattern.ends_with('\\') {
+        pattern.push(' ');
+    }
+    if pattern.is_empty() || pattern.starts_with('#') {
+        return None;
+    }
+    let negated = pattern.starts_with('!');
+    let mut pattern = if negated { &pattern[1..] } else { &pattern[..] };
+    if let Some(escaped) = pattern.strip_prefix('\\').filter(|rest| rest.starts_with(['#', '!'])) {
+        pattern = escaped;
+    }
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    let regex = Regex::new(&glob::to_regex(pattern).ok()?).ok()?;
+    Some(Rule {
+        regex,
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+impl IgnoreRules {
+    pub fn parse(text: &str, base: &Path) -> IgnoreRules {
+        IgnoreRules {
+            base: base.to_path_buf(),
+            rules: text.lines().filter_map(parse_rule).collect(),
+        }
+    }
+
+    pub fn from_file(path: &Path, base: &Path) -> io::Result<IgnoreRules> {
+        Ok(IgnoreRules::parse(&fs::read_to_string(path)?, base))
+    }
+
+    // Some(true) if the last rule matching `path` ignores it, Some(false) if
+    // it re-includes it and None if no rule matches.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?.to_string_lossy().into_owned();
+        let name = path.file_name()?.to_string_lossy().into_owned();
+        self.rules.iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only) && rule.regex.is_match(if rule.anchored { &relative } else { &name })
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+// Rules from deeper directories take precedence, so the ignore files are
+// checked from the last one found to the first, and the --ignore-file ones,
+// in `extra`, only after all of them.
+pub fn is_ignored(extra: &[IgnoreRules], stack: &[IgnoreRules], path: &Path, is_dir: bool) -> bool {
+    stack.iter()
+        .rev()
+        .chain(extra.iter().rev())
+        .find_map(|rules| rules.matched(path, is_dir))
+        .unwrap_or(false)
+}
+
+// The ignore files read in each directory; for rules in the same directory,
+// .ignore wins over .gitignore.
+pub const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+// The rules that apply to a walk starting at `root` (an absolute path) before
+// any directory inside it is read: the user's global git excludes, the
+// repository's .git/info/exclude and the ignore files of the directories
+// from the top of the repository down to, but not including, `root`.
+pub fn outer_rules(root: &Path) -> Vec<IgnoreRules> {
+    let repository = root.ancestors().find(|dir| dir.join(".git").exists());
+    let top = repository.unwrap_or(root);
+    let mut stack = Vec::new();
+    if let Some(excludes) = crate::git::excludes_file() {
+        if let Ok(rules) = IgnoreRules::from_file(&excludes, top) {
+            stack.push(rules);
+        }
+    }
+    if let Some(repository) = repository {
+        if let Ok(rules) = IgnoreRules::from_file(&repository.join(".git/info/exclude"), repository) {
+            stack.push(rules);
+        }
+    }
+    let mut outer: Vec<&Path> = root.ancestors().skip(1).take_while(|dir| dir.starts_with(top)).collect();
+    outer.reverse();
+    for dir in outer {
+        push_dir_rules(&mut stack, dir);
+    }
+    stack
+}
+
+// Adds the rules of the ignore files in `dir`, if it has any.
+pub fn push_dir_rules(stack: &mut Vec<IgnoreRules>, dir: &Path) {
+    for name in IGNORE_FILES {
+        if let Ok(rules) = IgnoreRules::from_file(&dir.join(name), dir) {
+            stack.push(rules);
+        }
+    }
+}
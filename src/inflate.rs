@@ -0,0 +1,287 @@
+// A decoder for raw DEFLATE data (RFC 1951), the compression zip archives
+// use. It follows the structure of zlib's "puff" reference decoder: Huffman
+// codes are decoded a bit at a time from canonical code lengths, which is
+// slower than a table-driven decoder but small and easy to check.
+
+const MAX_BITS: usize = 15;
+
+// Base lengths and extra bits for length codes 257-285, and base distances
+// and extra bits for distance codes 0-29.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227,
+    258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// The order code length code lengths are stored in, in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a> {
+    input: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn take(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.input.get(self.position).ok_or("compressed data ends early")?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer = self.buffer.checked_shr(n).unwrap_or(0);
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Stored blocks start on a byte boundary.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// A canonical Huffman code: how many codes there are of each length, and the
+// symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=MAX_BITS {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code in compressed data".to_string())
+    }
+}
+
+fn inflate_block(bits: &mut Bits, output: &mut Vec<u8>, limit: usize, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        if output.len() > limit {
+            return Err(LIMIT_EXCEEDED.to_string());
+        }
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let length = LENGTH_BASE[index] as usize + bits.take(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distances.decode(bits)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err("invalid distance in compressed data".to_string());
+                }
+                let distance = DISTANCE_BASE[index] as usize + bits.take(DISTANCE_EXTRA[index] as u32)? as usize;
+                if distance > output.len() {
+                    return Err("distance too far back in compressed data".to_string());
+                }
+                // The copy may overlap what it produces, so go byte by byte.
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+            _ => return Err("invalid length code in compressed data".to_string()),
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let code_length_count = bits.take(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.take(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_length_code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..i].last().ok_or("repeated code length with no previous length")?;
+                (previous, 3 + bits.take(2)? as usize)
+            }
+            17 => (0, 3 + bits.take(3)? as usize),
+            _ => (0, 11 + bits.take(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err("too many code lengths in compressed data".to_string());
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+const LIMIT_EXCEEDED: &str = "decompressed data is longer than expected";
+
+// Decodes `input`, failing rather than producing more than `limit` bytes.
+pub fn inflate(input: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut bits = Bits {
+        input,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut output = Vec::new();
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.align();
+                let header = input.get(bits.position..bits.position + 4).ok_or("compressed data ends early")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                if u16::from_le_bytes([header[2], header[3]]) != !(length as u16) {
+                    return Err("invalid stored block length in compressed data".to_string());
+                }
+                bits.position += 4;
+                let stored = input.get(bits.position..bits.position + length).ok_or("compressed data ends early")?;
+                if output.len() + length > limit {
+                    return Err(LIMIT_EXCEEDED.to_string());
+                }
+                output.extend_from_slice(stored);
+                bits.position += length;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut bits, &mut output, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut output, limit, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type in compressed data".to_string()),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{inflate, LIMIT_EXCEEDED};
+
+    pub(crate) fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // What zlib's deflate makes of "line 0\n" to "line 99\n": one block with
+    // dynamic codes.
+    const DYNAMIC: &str = concat!(
+        "35d2bb6d43310c40d1de5364048bfa9103a530f0e0fdcb20d051752b1d48229fcff7f7e7fd7afed34ee2a49f8c9379b24ef6",
+        "499e94e397e13450233554633558a3355ce3052feebd78c10b5ef08217bce005aff33aafdf87f23aaff33aaff33aaff3066f",
+        "f0066fdc9fe30ddee00ddee00ddee44ddee44ddebca3e04ddee44ddee42ddee22ddee22ddebab3e52ddee22ddee66ddee66d",
+        "dee66ddebecbc2dbbccd4b5ef29297bce4252f7979b78f97bce215af78c52b5ef18a57bcbaeb5caf3f",
+    );
+
+    fn lines() -> Vec<u8> {
+        (0..100).map(|i| format!("line {}\n", i)).collect::<String>().into_bytes()
+    }
+
+    #[test]
+    fn stored_and_fixed_blocks() {
+        assert_eq!(inflate(&unhex("010d00f2ff68656c6c6f2c20776f726c640a"), 100).unwrap(), b"hello, world\n");
+        assert_eq!(inflate(&unhex("cb48cdc9c957c84027b900"), 100).unwrap(), b"hello hello hello hello\n");
+    }
+
+    #[test]
+    fn dynamic_blocks() {
+        assert_eq!(inflate(&unhex(DYNAMIC), usize::MAX).unwrap(), lines());
+    }
+
+    // zlib and gzip streams are raw deflate data between a header and a
+    // checksum: 2 bytes and 4 for zlib, 10 and 8 for gzip without options.
+    #[test]
+    fn zlib_and_gzip_streams() {
+        let zlib = unhex("789cabcac94c52284eac2c56c848cdc9c9e7020033f505d0");
+        assert_eq!(inflate(&zlib[2..zlib.len() - 4], 100).unwrap(), b"zlib says hello\n");
+        let gzip = unhex("1f8b08000000000002034bafca2c50284eac2c56c848cdc9c9e70200565e61a610000000");
+        assert_eq!(inflate(&gzip[10..gzip.len() - 8], 100).unwrap(), b"gzip says hello\n");
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let input = unhex(DYNAMIC);
+        assert_eq!(inflate(&input, 100).unwrap_err(), LIMIT_EXCEEDED);
+        assert_eq!(inflate(&unhex("010d00f2ff68656c6c6f2c20776f726c640a"), 12).unwrap_err(), LIMIT_EXCEEDED);
+        assert!(inflate(&input, lines().len()).is_ok());
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let input = unhex(DYNAMIC);
+        for end in 0..input.len() {
+            assert!(inflate(&input[..end], usize::MAX).is_err(), "decoded {} bytes of {}", end, input.len());
+        }
+    }
+
+    #[test]
+    fn corrupt_input_doesnt_panic() {
+        assert!(inflate(&unhex("010d000000"), 100).is_err());
+        assert!(inflate(&[0x07], 100).is_err());
+        let input = unhex(DYNAMIC);
+        for at in 0..input.len() {
+            for bit in 0..8 {
+                let mut corrupt = input.clone();
+                corrupt[at] ^= 1 << bit;
+                let _ = inflate(&corrupt, 1 << 16);
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::output;
+use crate::stats::Stats;
+
+// Messages follow the schema of ripgrep's --json output so that tools written
+// against it can consume minigrep's output unchanged.
+
+static BYTES_PRINTED: AtomicUsize = AtomicUsize::new(0);
+
+// Prints one message per line and keeps count of the bytes written for the
+// bytes_printed statistic.
+pub fn emit(message: &str) {
+    output::print(format_args!("{}\n", message));
+    BYTES_PRINTED.fetch_add(message.len() + 1, Ordering::Relaxed);
+}
+
+pub fn bytes_printed() -> usize {
+    BYTES_PRINTED.load(Ordering::Relaxed)
+}
+
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn text_object(text: &str) -> String {
+    format!("{{\"text\":{}}}", string(text))
+}
+
+fn duration(elapsed: Duration) -> String {
+    format!(
+        "{{\"secs\":{},\"nanos\":{},\"human\":\"{:.6}s\"}}",
+        elapsed.as_secs(),
+        elapsed.subsec_nanos(),
+        elapsed.as_secs_f64()
+    )
+}
+
+fn stats(stats: &Stats) -> String {
+    format!(
+        "{{\"elapsed\":{},\"searches\":{},\"searches_with_match\":{},\"bytes_searched\":{},\"bytes_printed\":{},\"matched_lines\":{},\"matches\":{}}}",
+        duration(stats.elapsed),
+        stats.searches,
+        stats.searches_with_match,
+        stats.bytes_searched,
+        stats.bytes_printed,
+        stats.matched_lines,
+        stats.matches
+    )
+}
+
+pub fn begin(path: &str) -> String {
+    format!("{{\"type\":\"begin\",\"data\":{{\"path\":{}}}}}", text_object(path))
+}
+
+pub struct Submatch<'a> {
+    pub text: &'a str,
+    pub start: usize,
+    pub end: usize,
+}
+
+// `kind` is "match" for selected lines and "context" for context lines.
+pub fn line(kind: &str, path: &str, text: &str, line_number: usize, offset: usize, submatches: &[Submatch]) -> String {
+    let submatches: Vec<String> = submatches.iter()
+        .map(|m| format!("{{\"match\":{},\"start\":{},\"end\":{}}}", text_object(m.text), m.start, m.end))
+        .collect();
+    format!(
+        "{{\"type\":\"{}\",\"data\":{{\"path\":{},\"lines\":{},\"line_number\":{},\"absolute_offset\":{},\"submatches\":[{}]}}}}",
+        kind,
+        text_object(path),
+        text_object(text),
+        line_number,
+        offset,
+        submatches.join(",")
+    )
+}
+
+// One object per line for --captures-json: the file and line number, then
+// each capture group keyed by its name, or _N for unnamed group N. Groups that
+// didn't participate in the match are null.
+pub fn captures(path: &str, line_number: usize, groups: &[(String, Option<&str>)]) -> String {
+    let mut object = format!("{{\"file\":{},\"line\":{}", string(path), line_number);
+    for (key, value) in groups {
+        let value = value.map_or("null".to_string(), string);
+        let _ = write!(object, ",{}:{}", string(key), value);
+    }
+    object.push('}');
+    object
+}
+
+pub fn end(path: &str, file_stats: &Stats) -> String {
+    format!(
+        "{{\"type\":\"end\",\"data\":{{\"path\":{},\"binary_offset\":null,\"stats\":{}}}}}",
+        text_object(path),
+        stats(file_stats)
+    )
+}
+
+pub fn summary(total: &Stats, elapsed_total: Duration) -> String {
+    format!(
+        "{{\"data\":{{\"elapsed_total\":{},\"stats\":{}}},\"type\":\"summary\"}}",
+        duration(elapsed_total),
+        stats(total)
+    )
+}
@@ -1,146 +1,1733 @@
+use std::collections::VecDeque;
 use std::env;
 use std::fmt::{Display, Formatter, Error};
 use std::fs::File;
+use std::io;
 use std::io::BufReader;
 use std::io::prelude::*;
+use std::ops::Range;
 use std::path::Path;
-use regex::Regex;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-fn print_usage() {
-    let args: Vec<String> = env::args().collect();
-    let prog_name = &args[0];
-    eprintln!("usage: {} [options] query file", prog_name);
-    eprintln!("");
-    eprintln!("file: file path");
-    eprintln!("query: search string as regex");
-    eprintln!("options:");
-    eprintln!("    -v: invert match: print lines that do not match instead");
-    eprintln!("    -g: dump regex capture groups");
-    eprintln!("");
-    eprintln!("Author: Ethan Faust");
-    eprintln!("");
+mod archive;
+mod args;
+mod chunked;
+mod color;
+mod completions;
+mod decompress;
+mod encoding;
+#[cfg(feature = "fancy")]
+mod fancy;
+mod format;
+mod git;
+mod glob;
+mod hexdump;
+mod inflate;
+mod hyperlink;
+mod ignore;
+mod json;
+mod matcher;
+mod mmap;
+#[cfg(feature = "net")]
+mod net;
+#[cfg(feature = "office")]
+mod office;
+mod output;
+mod parallel;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod prefilter;
+mod process;
+mod progress;
+mod rewrite;
+mod stats;
+mod types;
+mod walk;
+
+use args::Action;
+use color::Style;
+use matcher::{MatchIter, Matcher, MatcherConfig, Span};
+use mmap::Mmap;
+use stats::Stats;
+
+// Exit statuses follow grep: 0 if any line was selected, 1 if none was, and 2
+// if an error occurred.
+const EXIT_MATCH: i32 = 0;
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+static HAD_ERROR: AtomicBool = AtomicBool::new(false);
+
+// Under --max-memory, threads are only searched on while each still has at
+// least this much to read with.
+const MIN_READ_MEMORY: usize = 1 << 20;
+
+// How much of --max-memory each thread searching may use for what it reads.
+fn read_memory(options: &MinigrepOptions) -> Option<usize> {
+    options.max_memory.map(|budget| budget / 2 / options.threads)
 }
 
+// Reports a problem with one input (an unreadable file or directory) without
+// stopping the search; the run still exits with EXIT_ERROR at the end.
+fn warn(options: &MinigrepOptions, message: &str) {
+    HAD_ERROR.store(true, Ordering::Relaxed);
+    if !options.no_messages {
+        progress::clear_for_message();
+        eprintln!("{}", message);
+    }
+}
+
+#[derive(Default)]
 struct MinigrepOptions {
-    filename: String,
-    query: String,
+    filenames: Vec<String>,
+    recursive: bool,
+    no_messages: bool,
+    verbose: bool,
+    color: bool,
+    heading: bool,
+    vimgrep: bool,
+    json: bool,
+    format: Option<format::OutputFormat>,
+    palette: color::Palette,
+    hyperlink: Option<hyperlink::HyperlinkFormat>,
+    files_from: Option<String>,
+    files_from_null: bool,
+    with_filename: bool,
+    null_data: bool,
+    // Printed records end in "\r\n" instead of "\n".
+    crlf: bool,
+    null: bool,
+    patterns: Vec<String>,
     invert_match: bool,
     dump_capture_groups: bool,
+    captures_json: bool,
+    capture_delimiter: String,
+    case_insensitive: bool,
+    smart_case: bool,
+    line_number: bool,
+    column: bool,
+    count: bool,
+    count_matches: bool,
+    max_count: Option<usize>,
+    max_line_size: Option<usize>,
+    max_memory: Option<usize>,
+    quiet: bool,
+    only_matching: bool,
+    max_columns: Option<usize>,
+    trim: bool,
+    max_columns_preview: bool,
+    replace: Option<String>,
+    write: bool,
+    interactive: bool,
+    diff: bool,
+    backup_suffix: Option<String>,
+    overlapping: bool,
+    match_offsets: bool,
+    word_regexp: bool,
+    line_regexp: bool,
+    multiline: bool,
+    multiline_dotall: bool,
+    fixed_strings: bool,
+    engine: matcher::EngineChoice,
+    no_unicode: bool,
+    text: bool,
+    hex: bool,
+    search_zip: bool,
+    archives: bool,
+    pre: Option<String>,
+    max_depth: Option<usize>,
+    max_filesize: Option<u64>,
+    follow: bool,
+    one_file_system: bool,
+    hidden: bool,
+    no_ignore: bool,
+    ignore_files: Vec<ignore::IgnoreRules>,
+    git_tracked: bool,
+    files: bool,
+    filename_only: bool,
+    threads: usize,
+    mmap: bool,
+    no_sort: bool,
+    // Flush standard output after every line instead of when its buffer fills.
+    line_buffered: bool,
+    progress: bool,
+    stats: bool,
+    sort: Option<walk::SortKey>,
+    sort_reverse: bool,
+    rev: Option<String>,
+    history: bool,
+    pre_globs: Vec<glob::Glob>,
+    includes: Vec<glob::Glob>,
+    excludes: Vec<glob::Glob>,
+    exclude_dirs: Vec<glob::Glob>,
+    types: Vec<glob::Glob>,
+    types_not: Vec<glob::Glob>,
+    encoding: Option<encoding::Encoding>,
+    regex_size_limit: Option<usize>,
+    dfa_size_limit: Option<usize>,
+    after_context: usize,
+    passthru: bool,
+    before_context: usize,
+    context_separator: Option<String>,
+    byte_offset: bool,
+    files_with_matches: bool,
+    files_without_match: bool,
 }
 
-fn parse_args(args: &[String]) -> Result<MinigrepOptions, &str> {
-    let arg_count = args.len();
-    if arg_count < 3 {
-        return Err("not enough arguments");
+// Rust ignores SIGPIPE, which turns a closed stdout (e.g. piping into `head`)
+// into a panic on the next println!. Restoring the default handler lets the
+// process end quietly with the usual 128 + SIGPIPE status instead.
+#[cfg(unix)]
+fn reset_sigpipe() {
+    use std::os::raw::c_int;
+
+    const SIGPIPE: c_int = 13;
+    const SIG_DFL: usize = 0;
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
     }
+    unsafe {
+        signal(SIGPIPE, SIG_DFL);
+    }
+}
+
+#[cfg(not(unix))]
+fn reset_sigpipe() {}
 
-    let mut invert_match: bool = false;
-    let mut dump_capture_groups: bool = false;
-    for arg_index in 1..(arg_count - 2) {
-        let arg = &args[arg_index];
-        if arg == "-v" {
-            invert_match = true;
+fn main() {
+    reset_sigpipe();
+    let args: Vec<String> = env::args().collect();
+    let prog_name = &args[0];
+    let options = match args::parse_command_line(&args) {
+        Err(e) => {
+            eprintln!("{}", e);
+            args::print_usage(&mut io::stderr(), prog_name);
+            std::process::exit(EXIT_ERROR);
         }
-        if arg == "-g" {
-            dump_capture_groups = true;
+        Ok(Action::Help) => {
+            args::print_usage(&mut io::stdout(), prog_name);
+            return;
         }
+        Ok(Action::Version) => {
+            println!("minigrep {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Ok(Action::TypeList(list)) => {
+            print!("{}", list);
+            return;
+        }
+        Ok(Action::Completions(shell)) => {
+            match completions::generate(&shell) {
+                Ok(script) => print!("{}", script),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(EXIT_ERROR);
+                }
+            }
+            return;
+        }
+        Ok(Action::Search(opt)) => opt
+    };
+    if !options.line_buffered {
+        output::block_buffered();
+    }
+    if let Some(budget) = options.max_memory {
+        output::limit_captured(budget / 2);
     }
+    if options.progress {
+        progress::start(options.line_buffered);
+    }
+    let matched = run(&options);
+    progress::stop();
+    if let Err(e) = output::flush() {
+        eprintln!("error writing output: {}", e);
+        std::process::exit(EXIT_ERROR);
+    }
+    if HAD_ERROR.load(Ordering::Relaxed) && !(options.quiet && matched) {
+        std::process::exit(EXIT_ERROR);
+    }
+    std::process::exit(if matched { EXIT_MATCH } else { EXIT_NO_MATCH });
+}
 
-    let query = &args[arg_count - 2];
-    let filename = &args[arg_count - 1];
+fn match_line(_options: &MinigrepOptions, matcher: &Matcher, line: &str) -> bool {
+    matcher.is_match(line)
+}
 
-    Ok(MinigrepOptions {
-        filename: filename.to_string(),
-        query: query.to_string(),
-        invert_match: invert_match,
-        dump_capture_groups: dump_capture_groups,
-    })
+fn is_selected(options: &MinigrepOptions, is_match: bool) -> bool {
+    if options.invert_match {
+        !is_match
+    } else {
+        is_match
+    }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let options = match parse_args(&args) {
-        Err(_e) => {
-            print_usage();
-            std::process::exit(1);
-        }
-        Ok(opt) => opt
+// Inverted lines contain no matches, so they count once each regardless of mode.
+// With -U a match is counted on the line it starts on.
+fn count_line(options: &MinigrepOptions, matcher: &Matcher, line: &Line) -> usize {
+    if !options.count_matches || options.invert_match {
+        return 1;
+    }
+    match &line.spans {
+        Some(spans) => spans.iter().filter(|span| span.first).count(),
+        None => matcher.find_iter(&line.text).count(),
+    }
+}
+
+struct Line {
+    number: usize,
+    offset: usize,
+    text: String,
+    ending: &'static str,
+    // With -U, the parts of the line that matches over the whole input cover.
+    spans: Option<Vec<Span>>,
+}
+
+// The matches in a line, which with -U were found beforehand.
+fn line_matches<'r, 'h>(matcher: &'r Matcher, line: &'h Line) -> MatchIter<'r, 'h> {
+    match &line.spans {
+        Some(spans) => MatchIter::from_spans(&line.text, spans),
+        None => matcher.find_iter(&line.text),
+    }
+}
+
+// The matches in `text`, the part of a line that starts at byte `from`, as
+// ranges within it. Without -U they're looked for in the text itself.
+fn matches_within(matcher: &Matcher, line: &Line, from: usize, text: &str) -> Vec<Range<usize>> {
+    let Some(spans) = &line.spans else {
+        return matcher.find_iter(text).map(|m| m.range()).collect();
     };
-    run(&options);
+    let to = from + text.len();
+    spans.iter()
+        .filter(|span| span.start <= to && span.end >= from)
+        .map(|span| span.start.max(from) - from..span.end.min(to) - from)
+        .collect()
+}
+
+fn output_line(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line, is_match: bool) {
+    if !is_selected(options, is_match) {
+        return;
+    }
+
+    if options.json {
+        json_output(matcher, filename, line, "match");
+    } else if let Some(format) = &options.format {
+        let captures = matcher.captures(&line.text).map(|(captures, _)| captures);
+        print_record(options, &format.render(filename, line.number, &line.text, captures.as_ref()));
+    } else if options.captures_json {
+        captures_json_output(matcher, filename, line);
+    } else if options.dump_capture_groups {
+        write_capture_groups(options, matcher, filename, line);
+    } else if options.vimgrep {
+        vimgrep_output(options, matcher, filename, line);
+    } else if options.match_offsets {
+        match_offsets_output(options, matcher, filename, line);
+    } else if options.hex {
+        hex_output(options, matcher, filename, line);
+    } else if options.only_matching {
+        only_matching_output(options, matcher, filename, line);
+    } else {
+        normal_output(options, matcher, filename, line);
+    }
 }
 
-fn match_line(_options: &MinigrepOptions, re: &Regex, line: &str) -> bool {
-    let is_match = re.is_match(line);
-    return is_match;
+// Ends each printed line with a newline, with --crlf a CRLF, or with -z a NUL byte.
+fn print_record(options: &MinigrepOptions, record: &str) {
+    let terminator = if options.null_data {
+        "\0"
+    } else if options.crlf {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    output::print(format_args!("{}{}", record, terminator));
 }
 
-fn output_line(options: &MinigrepOptions, re: &Regex, line: &str, is_match: bool) {
-    let mut should_write = is_match;
+fn line_prefix(options: &MinigrepOptions, filename: &str, line: &Line, column: Option<usize>, byte_offset: usize) -> String {
+    prefix_with_separator(options, filename, line, column, byte_offset, ':')
+}
 
-    if options.invert_match {
-        should_write = !is_match;
+// Selected lines use ':' after each prefix field and context lines use '-', as grep does.
+fn prefix_with_separator(options: &MinigrepOptions, filename: &str, line: &Line, column: Option<usize>, byte_offset: usize, separator: char) -> String {
+    let mut prefix = String::new();
+    if options.with_filename && !options.heading {
+        let path = linked_path(options, filename, Some(line.number), column);
+        let separator = if options.null { '\0' } else { separator };
+        prefix.push_str(&format!("{}{}", path, separator));
     }
-    if !should_write {
-        return;
+    if options.line_number {
+        let number = line.number.to_string();
+        prefix.push_str(&format!("{}{}", styled(options, Style::LineNumber, &number), separator));
     }
+    if options.column {
+        if let Some(column) = column {
+            let column = column.to_string();
+            prefix.push_str(&format!("{}{}", styled(options, Style::Column, &column), separator));
+        }
+    }
+    if options.byte_offset {
+        prefix.push_str(&format!("{}{}", byte_offset, separator));
+    }
+    prefix
+}
+
+// Whether anything printed with these options shows line numbers: the
+// prefix, JSON and --format output, or the links around paths on each line.
+fn shows_line_numbers(options: &MinigrepOptions) -> bool {
+    options.line_number
+        || options.vimgrep
+        || options.json
+        || options.captures_json
+        || options.format.is_some()
+        || (options.color && options.with_filename && options.hyperlink.as_ref().is_some_and(|format| format.uses_line()))
+}
 
-    if options.dump_capture_groups {
-        write_capture_groups(options, re, line);
+// Hyperlinks are escape sequences like colors, so they're only emitted when
+// color output is enabled.
+fn linked_path(options: &MinigrepOptions, filename: &str, line: Option<usize>, column: Option<usize>) -> String {
+    let text = styled(options, Style::Path, filename);
+    match &options.hyperlink {
+        Some(format) if options.color && filename != display_name("-") => {
+            format.wrap(&text, filename, line, column)
+        }
+        _ => text,
+    }
+}
+
+fn styled(options: &MinigrepOptions, style: Style, text: &str) -> String {
+    if options.color {
+        options.palette.paint(style, text)
     } else {
-        normal_output(options, line);
+        text.to_string()
     }
 }
 
-fn normal_output(_options: &MinigrepOptions, line: &str) {
-    println!("{}", &line);
+fn context_output(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line) {
+    if options.json {
+        json_output(matcher, filename, line, "context");
+        return;
+    }
+    let prefix = prefix_with_separator(options, filename, line, None, line.offset, '-');
+    print_record(options, &format!("{}{}", prefix, display_text(options, matcher, line)));
+}
+
+struct ContextTracker {
+    before: VecDeque<Line>,
+    after_remaining: usize,
+    last_printed: Option<usize>,
 }
 
-fn write_capture_groups(_options: &MinigrepOptions, re: &Regex, line: &str) {
-    let captures = re.captures(line);
-    if captures.is_none() {
+impl ContextTracker {
+    fn new() -> ContextTracker {
+        ContextTracker {
+            before: VecDeque::new(),
+            after_remaining: 0,
+            last_printed: None,
+        }
+    }
+
+    // Prints the group separator when the next printed line doesn't directly
+    // follow the previous one.
+    fn mark_printed(&mut self, options: &MinigrepOptions, line_number: usize) {
+        if let (Some(last), Some(separator)) = (self.last_printed, &options.context_separator) {
+            if line_number > last + 1 {
+                print_record(options, separator);
+            }
+        }
+        self.last_printed = Some(line_number);
+    }
+
+    // Returns the line that no longer needs remembering, if any, so its
+    // buffer can be reused.
+    fn remember(&mut self, options: &MinigrepOptions, line: Line) -> Option<Line> {
+        if options.before_context == 0 {
+            return Some(line);
+        }
+        let forgotten = if self.before.len() == options.before_context {
+            self.before.pop_front()
+        } else {
+            None
+        };
+        self.before.push_back(line);
+        forgotten
+    }
+
+    fn flush_before(&mut self, options: &MinigrepOptions, matcher: &Matcher, filename: &str) {
+        while let Some(line) = self.before.pop_front() {
+            self.mark_printed(options, line.number);
+            context_output(options, matcher, filename, &line);
+        }
+    }
+}
+
+// With --heading the file name is printed once above its first printed line
+// instead of on every line, and file groups are separated by a blank line.
+// JSON output opens each file's messages with a begin message instead.
+fn print_heading(options: &MinigrepOptions, filename: &str, printed: &mut bool) {
+    if *printed {
         return;
     }
-    let captures = captures.unwrap();
-    let matches : Vec<&str> = captures.iter()
-        .map(|c| c.map_or("", |m| m.as_str()))
+    if options.json {
+        json::emit(&json::begin(filename));
+        *printed = true;
+        return;
+    }
+    if !options.heading || !options.with_filename {
+        return;
+    }
+    output::heading_break();
+    output::print(format_args!("{}{}\n", linked_path(options, filename, None, None), path_terminator(options, "")));
+    *printed = true;
+}
+
+fn uses_context(options: &MinigrepOptions) -> bool {
+    (options.after_context > 0 || options.before_context > 0 || options.passthru)
+        && !options.only_matching
+        && !options.match_offsets
+        && !options.hex
+        && !options.vimgrep
+        && !options.dump_capture_groups
+        && !options.captures_json
+        && options.format.is_none()
+}
+
+fn first_match_column(matcher: &Matcher, line: &str) -> Option<usize> {
+    matcher.find(line).map(|m| m.start() + 1)
+}
+
+fn normal_output(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line) {
+    let first_match = line_matches(matcher, line).next();
+    let column = first_match.map(|m| m.start() + 1);
+    let prefix = line_prefix(options, filename, line, column, line.offset);
+    let text = match &options.replace {
+        Some(template) => replace_matches(options, matcher, &line.text, template),
+        None => display_text(options, matcher, line),
+    };
+    print_record(options, &format!("{}{}", prefix, text));
+}
+
+fn replace_matches(options: &MinigrepOptions, matcher: &Matcher, text: &str, template: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut last_end = 0;
+    for (m, replacement) in matcher.replacements(text, template) {
+        replaced.push_str(&text[last_end..m.start()]);
+        if !replacement.is_empty() {
+            replaced.push_str(&styled(options, Style::Match, &replacement));
+        }
+        last_end = m.end();
+    }
+    replaced.push_str(&text[last_end..]);
+    replaced
+}
+
+// Lines longer than --max-columns characters are replaced by a note saying how
+// many matches they hold, or with --max-columns-preview cut to their first
+// columns. With --trim, indentation is dropped first; prefixed columns still
+// count from the start of the untrimmed line.
+fn display_text(options: &MinigrepOptions, matcher: &Matcher, line: &Line) -> String {
+    let text = if options.trim { line.text.trim_start() } else { &line.text };
+    let from = line.text.len() - text.len();
+    let max = match options.max_columns {
+        Some(max) if text.chars().count() > max => max,
+        _ => return highlight_ranges(options, text, matches_within(matcher, line, from, text)),
+    };
+    let matches = matches_within(matcher, line, from, text);
+    let total = matches.iter().filter(|m| !m.is_empty()).count();
+    if !options.max_columns_preview {
+        return format!("[Omitted long line with {} matches]", total);
+    }
+    let cut = text.char_indices().nth(max).map_or(text.len(), |(i, _)| i);
+    let shown = matches.iter().filter(|m| !m.is_empty() && m.end <= cut).count();
+    let preview = highlight_ranges(options, &text[..cut], matches_within(matcher, line, from, &text[..cut]));
+    format!("{} [... {} more matches]", preview, total - shown)
+}
+
+fn highlight_matches(options: &MinigrepOptions, matcher: &Matcher, text: &str) -> String {
+    highlight_ranges(options, text, matcher.find_iter(text).map(|m| m.range()))
+}
+
+fn highlight_ranges(options: &MinigrepOptions, text: &str, matches: impl IntoIterator<Item = Range<usize>>) -> String {
+    if !options.color {
+        return text.to_string();
+    }
+    let mut highlighted = String::with_capacity(text.len());
+    let mut last_end = 0;
+    // With --overlapping a match may start inside the previous one; only its
+    // not yet highlighted remainder is colored.
+    for m in matches {
+        if m.end <= last_end || m.is_empty() {
+            continue;
+        }
+        let start = m.start.max(last_end);
+        highlighted.push_str(&text[last_end..start]);
+        highlighted.push_str(&styled(options, Style::Match, &text[start..m.end]));
+        last_end = m.end;
+    }
+    highlighted.push_str(&text[last_end..]);
+    highlighted
+}
+
+// One record per match rather than per line, as Vim's quickfix list expects.
+// Inverted lines have no match, so they get a single record without a column.
+fn vimgrep_output(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line) {
+    let mut printed = false;
+    for m in line_matches(matcher, line) {
+        let prefix = line_prefix(options, filename, line, Some(m.start() + 1), line.offset);
+        print_record(options, &format!("{}{}", prefix, display_text(options, matcher, line)));
+        printed = true;
+    }
+    if !printed {
+        print_record(options, &format!("{}{}", line_prefix(options, filename, line, None, line.offset), line.text));
+    }
+}
+
+// Lines keep their terminator in JSON output, as rg reports them.
+fn json_output(matcher: &Matcher, filename: &str, line: &Line, kind: &str) {
+    let submatches: Vec<json::Submatch> = line_matches(matcher, line)
+        .filter(|m| !m.as_str().is_empty())
+        .map(|m| json::Submatch { text: m.as_str(), start: m.start(), end: m.end() })
         .collect();
-    let capture_vec = CaptureGroupVec(matches);
-    println!("{}", capture_vec);
+    let text = format!("{}{}", line.text, line.ending);
+    json::emit(&json::line(kind, filename, &text, line.number, line.offset, &submatches));
+}
+
+fn only_matching_output(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line) {
+    if let Some(template) = &options.replace {
+        for (m, replacement) in matcher.replacements(&line.text, template) {
+            if m.as_str().is_empty() {
+                continue;
+            }
+            let prefix = line_prefix(options, filename, line, Some(m.start() + 1), line.offset + m.start());
+            print_record(options, &format!("{}{}", prefix, styled(options, Style::Match, &replacement)));
+        }
+        return;
+    }
+    for m in line_matches(matcher, line) {
+        if m.as_str().is_empty() {
+            continue;
+        }
+        let prefix = line_prefix(options, filename, line, Some(m.start() + 1), line.offset + m.start());
+        print_record(options, &format!("{}{}", prefix, styled(options, Style::Match, m.as_str())));
+    }
+}
+
+// Offsets are end-exclusive, so END - START is the length of the match in bytes.
+fn match_offsets_output(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line) {
+    for m in line_matches(matcher, line) {
+        if m.as_str().is_empty() {
+            continue;
+        }
+        let prefix = line_prefix(options, filename, line, Some(m.start() + 1), line.offset + m.start());
+        print_record(options, &format!("{}{}-{}:{}", prefix, m.start(), m.end(), styled(options, Style::Match, m.as_str())));
+    }
+}
+
+// With --hex every byte of the input was read as one Latin-1 character, so a
+// line's characters are its bytes and character counts are byte offsets.
+fn hex_output(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line) {
+    let bytes: Vec<u8> = line.text.chars().map(|c| c as u8).collect();
+    for m in line_matches(matcher, line) {
+        if m.as_str().is_empty() {
+            continue;
+        }
+        let start = line.text[..m.start()].chars().count();
+        let end = start + m.as_str().chars().count();
+        let from = start.saturating_sub(hexdump::CONTEXT_BYTES);
+        let to = (end + hexdump::CONTEXT_BYTES).min(bytes.len());
+        let prefix = line_prefix(options, filename, line, Some(start + 1), line.offset + start);
+        print_record(options, &format!("{}{:#x}-{:#x}", prefix, line.offset + start, line.offset + end));
+        let highlight = start - from..end - from;
+        for row in hexdump::rows(&bytes[from..to], line.offset + from, highlight, |s| styled(options, Style::Match, s)) {
+            print_record(options, &row);
+        }
+    }
+}
+
+fn write_capture_groups(options: &MinigrepOptions, matcher: &Matcher, filename: &str, line: &Line) {
+    let (captures, names) = match matcher.captures(&line.text) {
+        Some(found) => found,
+        None => return,
+    };
+    let groups: Vec<(Option<&str>, &str)> = names.into_iter().zip(captures.iter())
+        .map(|(name, c)| (name, c.map_or("", |m| m.as_str())))
+        .collect();
+    let capture_vec = CaptureGroupVec {
+        groups,
+        delimiter: &options.capture_delimiter,
+    };
+    let column = first_match_column(matcher, &line.text);
+    print_record(options, &format!("{}{}", line_prefix(options, filename, line, column, line.offset), capture_vec));
+}
+
+fn captures_json_output(matcher: &Matcher, filename: &str, line: &Line) {
+    let (captures, names) = match matcher.captures(&line.text) {
+        Some(found) => found,
+        None => return,
+    };
+    let groups: Vec<(String, Option<&str>)> = names.into_iter().zip(captures.iter())
+        .enumerate()
+        .skip(1)
+        .map(|(i, (name, c))| {
+            let key = name.map_or_else(|| format!("_{}", i), str::to_string);
+            (key, c.map(|m| m.as_str()))
+        })
+        .collect();
+    output::print(format_args!("{}\n", json::captures(filename, line.number, &groups)));
+}
+
+// Group 0 is the whole match and is only printed when the pattern has no groups
+// of its own. Named groups print as name=value.
+struct CaptureGroupVec<'a> {
+    groups: Vec<(Option<&'a str>, &'a str)>,
+    delimiter: &'a str,
 }
 
-struct CaptureGroupVec<'a>(Vec<& 'a str>);
 impl Display for CaptureGroupVec<'_> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        let mut comma_separated = String::new();
-        for capture in &self.0[1..self.0.len() - 1] {
-            comma_separated.push_str(&capture);
-            comma_separated.push_str(",");
+        let groups = if self.groups.len() > 1 { &self.groups[1..] } else { &self.groups[..] };
+        let fields: Vec<String> = groups.iter()
+            .map(|(name, value)| {
+                let field = match name {
+                    Some(name) => format!("{}={}", name, value),
+                    None => value.to_string(),
+                };
+                quote_field(&field, self.delimiter)
+            })
+            .collect();
+        write!(f, "{}", fields.join(self.delimiter))
+    }
+}
+
+// Fields that contain the delimiter, a quote or a line break are quoted CSV
+// style, with embedded quotes doubled, so the output splits unambiguously.
+fn quote_field(field: &str, delimiter: &str) -> String {
+    let needs_quotes = (!delimiter.is_empty() && field.contains(delimiter))
+        || field.contains('"')
+        || field.contains('\n')
+        || field.contains('\r');
+    if needs_quotes {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Escaped characters are skipped so classes like \S or \W don't count as uppercase.
+fn has_uppercase(query: &str) -> bool {
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+fn ignore_case(options: &MinigrepOptions) -> bool {
+    let uppercase = options.patterns.iter().any(|query| {
+        if options.fixed_strings {
+            query.chars().any(char::is_uppercase)
+        } else {
+            has_uppercase(query)
+        }
+    });
+    options.case_insensitive || (options.smart_case && !uppercase)
+}
+
+// Half word boundaries only look outside the match, so patterns that begin or end
+// with non-word characters (e.g. "@user") still work with -w.
+fn build_pattern(options: &MinigrepOptions, query: &str) -> String {
+    let mut pattern = if options.fixed_strings {
+        regex::escape(query)
+    } else {
+        query.to_string()
+    };
+    if options.line_regexp {
+        pattern = format!("^(?:{})$", pattern);
+    } else if options.word_regexp {
+        pattern = format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern);
+    }
+    pattern
+}
+
+// Matches the line splitting of BufRead::lines, which strips "\n" or "\r\n".
+// With -z records end in NUL instead. Returns the terminator that was removed.
+fn trim_line_ending(options: &MinigrepOptions, text: &mut String) -> &'static str {
+    if options.null_data {
+        if text.ends_with('\0') {
+            text.pop();
+            return "\0";
+        }
+        return "";
+    }
+    if !text.ends_with('\n') {
+        return "";
+    }
+    text.pop();
+    if text.ends_with('\r') {
+        text.pop();
+        return "\r\n";
+    }
+    "\n"
+}
+
+fn max_count_reached(options: &MinigrepOptions, selected_lines: usize) -> bool {
+    matches!(options.max_count, Some(max) if selected_lines >= max)
+}
+
+// Modes that only care whether a file matches at all can stop at the first match.
+fn stops_at_first_match(options: &MinigrepOptions) -> bool {
+    options.quiet || options.files_with_matches || options.files_without_match
+}
+
+fn run(options: &MinigrepOptions) -> bool {
+    if options.files {
+        return list_files(options);
+    }
+    let patterns: Vec<String> = options.patterns.iter()
+        .map(|query| build_pattern(options, query))
+        .collect();
+    let config = MatcherConfig {
+        case_insensitive: ignore_case(options),
+        overlapping: options.overlapping,
+        multi_line: options.multiline,
+        dot_all: options.multiline && options.multiline_dotall,
+        engine: options.engine,
+        no_unicode: options.no_unicode,
+        size_limit: options.regex_size_limit,
+        dfa_size_limit: options.dfa_size_limit,
+    };
+    let matcher = Matcher::new(&patterns, &config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(EXIT_ERROR);
+    });
+    if options.verbose && options.engine == matcher::EngineChoice::Auto && matcher.engine_name() == "fancy" {
+        eprintln!("note: the default regex engine doesn't support this pattern; using the fancy engine");
+    }
+
+    if options.filename_only {
+        return match_file_names(options, &matcher);
+    }
+
+    let start = Instant::now();
+    let mut total = Stats::default();
+    if options.history {
+        total = git::search_history(options, &matcher, &options.filenames);
+    } else if let Some(rev) = &options.rev {
+        total = git::search_revision(options, &matcher, rev, &options.filenames);
+    } else {
+        if options.threads > 1 && !options.interactive && !single_file(options) {
+            total = parallel::search_files(options, &matcher);
+        } else {
+            for_each_file(options, &mut |filename| search_counted(options, &matcher, filename, &mut total));
         }
-        comma_separated.push_str(&self.0[self.0.len() - 1]);
-        write!(f, "{}", comma_separated)
     }
+    if options.json {
+        total.bytes_printed = json::bytes_printed();
+        json::emit(&json::summary(&total, start.elapsed()));
+    } else if options.stats {
+        let separator = if options.quiet { "" } else { "\n" };
+        output::print(format_args!("{}{}", separator, total.summary(start.elapsed())));
+    }
+    total.matched_lines > 0
+}
+
+// Whether the arguments name just one file, which is searched on this thread
+// so that the other threads can search pieces of it instead.
+fn single_file(options: &MinigrepOptions) -> bool {
+    options.files_from.is_none() && options.filenames.len() == 1 && !Path::new(&options.filenames[0]).is_dir()
 }
 
-fn run(options: &MinigrepOptions) {
-    let path = Path::new(&options.filename);
-    let path_display = path.display();
-    let file = File::open(&path).unwrap_or_else(|e| {
-        eprintln!("couldn't open {}: {}", path_display, e);
-        std::process::exit(1);
+// Calls `act` for each file named by the arguments or --files-from, walking
+// directories with -r, until it returns false.
+fn for_each_file(options: &MinigrepOptions, act: &mut dyn FnMut(&str) -> bool) {
+    // With --sort every file is found before any is acted on, so they can be
+    // put in order first.
+    let mut sorted_files = Vec::new();
+    let mut visit = |filename: &str| {
+        if options.sort.is_some() {
+            sorted_files.push(filename.to_string());
+            return true;
+        }
+        act(filename)
+    };
+    let mut search_path = |filename: &str| {
+        let path = Path::new(filename);
+        if options.recursive && path.is_dir() {
+            walk::walk(options, path, &mut |file: &Path| visit(&file.to_string_lossy()))
+        } else if filename == "-" || is_url(filename) || walk::file_wanted(options, path) {
+            visit(filename)
+        } else {
+            true
+        }
+    };
+    let mut keep_going = true;
+    for filename in &options.filenames {
+        keep_going = search_path(filename);
+        if !keep_going {
+            break;
+        }
+    }
+    if keep_going {
+        if let Some(list) = &options.files_from {
+            read_file_list(options, list, &mut search_path);
+        }
+    }
+    if let Some(key) = options.sort {
+        walk::sort_files(&mut sorted_files, key, options.sort_reverse);
+        for filename in &sorted_files {
+            if !act(filename) {
+                break;
+            }
+        }
+    }
+}
+
+// For --files: prints the name of every file that would be searched.
+// Returns whether there were any.
+fn list_files(options: &MinigrepOptions) -> bool {
+    let mut listed = false;
+    for_each_file(options, &mut |filename| {
+        let path = linked_path(options, filename, None, None);
+        output::print(format_args!("{}{}", path, path_terminator(options, "\n")));
+        listed = true;
+        true
     });
-    let reader = BufReader::new(file);
+    listed
+}
 
-    let re = Regex::new(&options.query).unwrap_or_else(|e| {
-        eprintln!("error parsing pattern {}: {}", &options.query, e);
-        std::process::exit(1);
+// For --filename-only: prints the path of every file that would be searched
+// and that the patterns match (or with -v don't), like find piped into grep.
+// Returns whether any did.
+fn match_file_names(options: &MinigrepOptions, matcher: &Matcher) -> bool {
+    let mut matched = false;
+    for_each_file(options, &mut |filename| {
+        if !is_selected(options, match_line(options, matcher, filename)) {
+            return true;
+        }
+        matched = true;
+        if !options.quiet {
+            output::print(format_args!("{}{}", highlight_matches(options, matcher, filename), path_terminator(options, "\n")));
+        }
+        !options.quiet
     });
+    matched
+}
+
+// Searches a file and adds its stats to `total`. Returns false if the search
+// should stop, because -q only needs one match.
+fn search_counted(options: &MinigrepOptions, matcher: &Matcher, filename: &str, total: &mut Stats) -> bool {
+    let file_stats = search_and_report(options, matcher, filename);
+    let matched = file_stats.matched_lines > 0;
+    total.add(&file_stats);
+    !(options.quiet && matched)
+}
+
+// Reads newline (or with -0, NUL) separated file names and searches each one as
+// it is read, so a long list from another tool is processed incrementally.
+fn read_file_list(options: &MinigrepOptions, list: &str, search_path: &mut dyn FnMut(&str) -> bool) {
+    let mut reader = match open_input(list) {
+        Ok(reader) => reader,
+        Err(e) => {
+            warn(options, &format!("couldn't open {}: {}", display_name(list), e));
+            return;
+        }
+    };
+    let delimiter = if options.files_from_null { b'\0' } else { b'\n' };
+    let mut entry = Vec::new();
+    loop {
+        entry.clear();
+        let bytes_read = match reader.read_until(delimiter, &mut entry) {
+            Ok(bytes_read) => bytes_read,
+            Err(e) => {
+                warn(options, &format!("error reading {}: {}", display_name(list), e));
+                break;
+            }
+        };
+        if bytes_read == 0 {
+            break;
+        }
+        if entry.last() == Some(&delimiter) {
+            entry.pop();
+        }
+        if !options.files_from_null && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        if entry.is_empty() {
+            continue;
+        }
+        if !search_path(&String::from_utf8_lossy(&entry)) {
+            break;
+        }
+    }
+}
+
+fn search_and_report(options: &MinigrepOptions, matcher: &Matcher, filename: &str) -> Stats {
+    let file_stats = if let (true, Some(template)) = (options.write || options.diff, &options.replace) {
+        rewrite::rewrite_file(options, matcher, template, filename)
+    } else if let Some(archive_stats) = Some(filename).filter(|_| options.archives)
+        .and_then(|filename| archive::search_archive(options, matcher, filename))
+    {
+        archive_stats
+    } else {
+        let file_stats = search_file(options, matcher, filename);
+        report_file(options, display_name(filename), &file_stats);
+        file_stats
+    };
+    progress::add_file();
+    file_stats
+}
 
-    for line in reader.lines() {
-        let line = line.unwrap_or_else(|e| {
-            eprintln!("error reading file: {}", e);
-            std::process::exit(1);
+// Searches one of several inputs that make up a larger one, like a file in an
+// archive, and adds its statistics to `total`. Returns whether the search
+// should stop, which it does after the first match with -q.
+fn search_member<'a>(
+    options: &MinigrepOptions,
+    matcher: &Matcher,
+    name: &str,
+    reader: Box<dyn BufRead + 'a>,
+    total: &mut Stats,
+) -> bool {
+    let member_stats = search_reader(options, matcher, name, reader, Instant::now());
+    report_file(options, name, &member_stats);
+    total.add(&member_stats);
+    options.quiet && member_stats.matched_lines > 0
+}
+
+// Prints the name of a searched file for -l or -L.
+fn report_file(options: &MinigrepOptions, filename: &str, file_stats: &Stats) {
+    let matched = file_stats.matched_lines > 0;
+    if !options.quiet
+        && ((options.files_with_matches && matched) || (options.files_without_match && !matched))
+    {
+        let path = linked_path(options, filename, None, None);
+        output::print(format_args!("{}{}", path, path_terminator(options, "\n")));
+    }
+}
+
+// With --null every printed file name ends in a NUL byte instead of whatever
+// would normally follow it, so names containing newlines survive `xargs -0`.
+fn path_terminator<'a>(options: &MinigrepOptions, normal: &'a str) -> &'a str {
+    if options.null {
+        "\0"
+    } else {
+        normal
+    }
+}
+
+fn display_name(filename: &str) -> &str {
+    if filename == "-" {
+        "(standard input)"
+    } else {
+        filename
+    }
+}
+
+fn open_input(filename: &str) -> io::Result<Box<dyn BufRead>> {
+    if filename == "-" {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    let file = File::open(filename)?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+// Splits every match in `content` into the parts of the lines it covers,
+// each paired with the index of its line and in line order. A match that
+// ends with a line terminator doesn't extend to the following line.
+fn line_spans(matcher: &Matcher, content: &str, terminator: u8) -> Vec<(usize, Span)> {
+    let mut starts = vec![0];
+    starts.extend(content.bytes()
+        .enumerate()
+        .filter(|&(i, b)| b == terminator && i + 1 < content.len())
+        .map(|(i, _)| i + 1));
+    let line_of = |pos: usize| starts.partition_point(|&start| start <= pos) - 1;
+    let mut spans = Vec::new();
+    for m in matcher.find_iter(content) {
+        let first = line_of(m.start());
+        let last = line_of(m.end().saturating_sub(1).max(m.start()));
+        for index in first..=last {
+            let start = starts[index];
+            spans.push((index, Span {
+                start: m.start().max(start) - start,
+                end: m.end().min(starts.get(index + 1).map_or(content.len(), |&next| next)) - start,
+                first: index == first,
+            }));
+        }
+    }
+    // With --overlapping a later match can start on an earlier line than
+    // the previous one ends on.
+    spans.sort_by_key(|&(index, span)| (index, span.start));
+    spans
+}
+
+// The --pre command is run on every file, or with --pre-glob only on files
+// matching one of the globs. Standard input is always searched as it is.
+fn preprocessor_for<'a>(options: &'a MinigrepOptions, filename: &str) -> Option<&'a str> {
+    let pre = options.pre.as_deref()?;
+    let path = Path::new(filename);
+    let applies = filename != "-" && (options.pre_globs.is_empty() || options.pre_globs.iter().any(|glob| glob.is_match(path)));
+    Some(pre).filter(|_| applies)
+}
+
+fn is_url(filename: &str) -> bool {
+    filename.starts_with("http://") || filename.starts_with("https://")
+}
+
+#[cfg(feature = "net")]
+fn open_url(url: &str) -> io::Result<Box<dyn BufRead>> {
+    net::open(url)
+}
+
+#[cfg(not(feature = "net"))]
+fn open_url(_url: &str) -> io::Result<Box<dyn BufRead>> {
+    Err(io::Error::other("can't fetch URLs: minigrep was built without the \"net\" feature"))
+}
+
+// Built with the "pdf" or "office" features, documents of those kinds are
+// searched by the text they contain.
+#[cfg(any(feature = "pdf", feature = "office"))]
+fn document_text(filename: &str) -> Option<Result<String, String>> {
+    #[cfg(feature = "pdf")]
+    if pdf::is_document(filename) {
+        return Some(pdf::text(filename));
+    }
+    #[cfg(feature = "office")]
+    if office::is_document(filename) {
+        return Some(office::text(filename));
+    }
+    None
+}
+
+#[cfg(not(any(feature = "pdf", feature = "office")))]
+fn document_text(_filename: &str) -> Option<Result<String, String>> {
+    None
+}
+
+fn search_file(options: &MinigrepOptions, matcher: &Matcher, filename: &str) -> Stats {
+    let start = Instant::now();
+    let opened = if is_url(filename) {
+        open_url(filename)
+    } else if let Some(pre) = preprocessor_for(options, filename) {
+        File::open(filename).and_then(|file| {
+            let mut command = Command::new(pre);
+            command.arg(filename).stdin(file);
+            process::spawn_reader(command, pre)
+        })
+    } else if let Some(text) = document_text(filename) {
+        text.map(|text| Box::new(io::Cursor::new(text.into_bytes())) as Box<dyn BufRead>)
+            .map_err(io::Error::other)
+    } else if options.search_zip && filename != "-" {
+        decompress::open(filename).transpose().unwrap_or_else(|| open_input(filename))
+    } else if let Some(file_stats) = chunked::search_file(options, matcher, filename, start) {
+        return file_stats;
+    } else if let Some(map) = Some(filename).filter(|_| options.mmap && filename != "-").and_then(Mmap::open) {
+        return search_input(options, matcher, filename, Input::Mapped(map.bytes()), start, None);
+    } else {
+        open_input(filename)
+    };
+    match opened {
+        Ok(reader) => search_reader(options, matcher, display_name(filename), reader, start),
+        Err(e) => {
+            warn(options, &format!("couldn't open {}: {}", filename, e));
+            Stats::default()
+        }
+    }
+}
+
+// Searches one input, which `filename` names in the output. `start` is when
+// the search of it began, for the elapsed time in its statistics.
+fn search_reader<'a>(
+    options: &MinigrepOptions,
+    matcher: &Matcher,
+    filename: &str,
+    reader: Box<dyn BufRead + 'a>,
+    start: Instant,
+) -> Stats {
+    search_input(options, matcher, filename, Input::Reader(reader), start, None)
+}
+
+// What search_input reads: a stream, or the bytes of a file mapped with
+// --mmap or read whole, which lines are sliced out of instead of copied.
+enum Input<'a> {
+    Reader(Box<dyn BufRead + 'a>),
+    Mapped(&'a [u8]),
+}
+
+impl<'a> Input<'a> {
+    // The start of the input, checked for a byte order mark and NUL bytes:
+    // what's buffered of a stream, or as much of a map as would be.
+    fn head(&mut self) -> Option<&[u8]> {
+        match self {
+            Input::Reader(reader) => reader.fill_buf().ok(),
+            Input::Mapped(bytes) => Some(&bytes[..bytes.len().min(8192)]),
+        }
+    }
+
+    fn as_reader(&mut self) -> &mut dyn BufRead {
+        match self {
+            Input::Reader(reader) => &mut **reader,
+            Input::Mapped(bytes) => bytes,
+        }
+    }
+
+    // Like read_record, but a record of mapped bytes is a slice of them,
+    // unless it's cut short by `limit`.
+    fn read_record<'r>(&'r mut self, terminator: u8, buffer: &'r mut Vec<u8>, limit: Option<usize>) -> io::Result<(&'r [u8], usize, bool)> {
+        let bytes = match self {
+            Input::Reader(reader) => {
+                buffer.clear();
+                let (bytes_read, truncated) = read_record(&mut **reader, terminator, buffer, limit)?;
+                return Ok((buffer, bytes_read, truncated));
+            }
+            Input::Mapped(bytes) => bytes,
+        };
+        let len = memchr::memchr(terminator, bytes).map_or(bytes.len(), |i| i + 1);
+        let (record, rest) = bytes.split_at(len);
+        *bytes = rest;
+        let ended = record.last() == Some(&terminator);
+        match limit.filter(|&limit| len - ended as usize > limit) {
+            None => Ok((record, len, false)),
+            Some(limit) => {
+                buffer.clear();
+                buffer.extend_from_slice(&record[..limit]);
+                if ended {
+                    buffer.push(terminator);
+                }
+                Ok((buffer, len, true))
+            }
+        }
+    }
+}
+
+// A piece of a large file that chunked::search_file searches on its own.
+// Its lines are numbered from `line_number` and its bytes from `offset`, and
+// it prints neither the file's heading nor its -c count, which is left in
+// `count` instead. `binary` says whether the file is known to be binary by
+// the start of the piece, and afterwards by its end. `stopped` is set if the
+// search of the file as a whole should end with this piece, because it
+// printed that the file matches.
+struct Chunk {
+    line_number: usize,
+    offset: usize,
+    count: usize,
+    binary: bool,
+    stopped: bool,
+}
+
+// Reads the rest of the input, for -U or --encoding. Returns None after a
+// warning if it can't be read or is bigger than --max-memory allows.
+fn read_whole(options: &MinigrepOptions, filename: &str, reader: &mut dyn BufRead) -> Option<Vec<u8>> {
+    let limit = read_memory(options);
+    let mut bytes = Vec::new();
+    let result = match limit {
+        Some(limit) => Read::take(reader, limit as u64 + 1).read_to_end(&mut bytes),
+        None => reader.read_to_end(&mut bytes),
+    };
+    if let Err(e) = result {
+        warn(options, &format!("error reading {}: {}", filename, e));
+        return None;
+    }
+    if limit.is_some_and(|limit| bytes.len() > limit) {
+        warn(options, &format!("skipping {}: it's too big to be read whole within --max-memory", filename));
+        return None;
+    }
+    Some(bytes)
+}
+
+// Reads a record up to and including its terminator like read_until, but
+// keeps no more than `limit` bytes of it before the terminator, reading past
+// the rest. Returns how many bytes were read and whether any were dropped.
+fn read_record(reader: &mut dyn BufRead, terminator: u8, record: &mut Vec<u8>, limit: Option<usize>) -> io::Result<(usize, bool)> {
+    let Some(limit) = limit else {
+        return reader.read_until(terminator, record).map(|bytes_read| (bytes_read, false));
+    };
+    let mut bytes_read = 0;
+    let mut truncated = false;
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if buf.is_empty() {
+            return Ok((bytes_read, truncated));
+        }
+        let (len, ended) = match memchr::memchr(terminator, buf) {
+            Some(i) => (i, true),
+            None => (buf.len(), false),
+        };
+        let kept = len.min(limit.saturating_sub(record.len()));
+        truncated = truncated || kept < len;
+        record.extend_from_slice(&buf[..kept]);
+        if ended {
+            record.push(terminator);
+        }
+        let used = len + ended as usize;
+        reader.consume(used);
+        bytes_read += used;
+        if ended {
+            return Ok((bytes_read, truncated));
+        }
+    }
+}
+
+// search_reader for either a whole input or, given `chunk`, a piece of one.
+fn search_input<'a>(
+    options: &MinigrepOptions,
+    matcher: &Matcher,
+    filename: &str,
+    input: Input<'a>,
+    start: Instant,
+    mut chunk: Option<&mut Chunk>,
+) -> Stats {
+    let terminator = if options.null_data { b'\0' } else { b'\n' };
+    // What's read whole, for --encoding or -U, is then searched like a map.
+    let (decoded, content);
+    let mut input = input;
+
+    // A byte order mark is dropped so it can't get in the way of a ^ on the
+    // first line, and takes precedence over --encoding. Files are only split
+    // into chunks if they don't start with one.
+    let mut encoding = options.encoding;
+    if chunk.is_none() {
+        if let Some((marked, len)) = input.head().and_then(encoding::sniff_bom) {
+            input.as_reader().consume(len);
+            encoding = Some(marked).filter(|&marked| marked != encoding::Encoding::Utf8);
+        }
+    }
+
+    // Other encodings are decoded up front, since line terminators can only
+    // be found in the decoded text (in UTF-16 they are two bytes wide).
+    if let Some(encoding) = encoding {
+        let Some(bytes) = read_whole(options, filename, input.as_reader()) else {
+            return Stats::default();
+        };
+        decoded = encoding.decode(&bytes).into_bytes();
+        input = Input::Mapped(&decoded);
+    }
+
+    // A file with a NUL byte is taken to be binary: its lines aren't printed,
+    // only a note that it matched. The first block read is checked up front
+    // and later lines as they're read.
+    let detect_binary = !options.text && !options.null_data;
+    let mut binary = detect_binary
+        && match &chunk {
+            Some(chunk) => chunk.binary,
+            None => input.head().is_some_and(|head| head.contains(&0)),
+        };
+
+    // With -U the patterns run over the whole input so matches can span lines;
+    // the lines a match touches are then reported as if each had matched, with
+    // the part of the match in each. A mapped file is matched where it is.
+    let mut multiline_spans = None;
+    let mut next_span = 0;
+    if options.multiline {
+        let bytes = match input {
+            Input::Mapped(bytes) => bytes,
+            Input::Reader(mut reader) => {
+                let Some(bytes) = read_whole(options, filename, &mut *reader) else {
+                    return Stats::default();
+                };
+                content = bytes;
+                &content
+            }
+        };
+        multiline_spans = Some(line_spans(matcher, &String::from_utf8_lossy(bytes), terminator));
+        input = Input::Mapped(bytes);
+    }
+
+    let with_context = uses_context(options);
+    let mut context = ContextTracker::new();
+    let mut heading_printed = chunk.is_some();
+    let mut selected_lines: usize = 0;
+    let mut selected_count: usize = 0;
+    let mut match_count: usize = 0;
+    let printed_before = json::bytes_printed();
+    let mut line_number = chunk.as_ref().map_or(0, |chunk| chunk.line_number);
+    let first_offset = chunk.as_ref().map_or(0, |chunk| chunk.offset);
+    let mut offset = first_offset;
+    // Lines that can't match are only skipped when nothing else would be
+    // printed for them. Under --hex lines are matched as Latin-1, which the
+    // literals found in the patterns don't allow for.
+    let prefilter = matcher.prefilter()
+        .filter(|_| !options.invert_match && !with_context && !options.passthru && !options.hex && multiline_spans.is_none());
+    // Each line is read and decoded into buffers kept from the lines before,
+    // rather than allocating new ones for every line.
+    let mut record_buffer = Vec::new();
+    let mut spare_text = String::new();
+    // How much has been counted towards --progress so far.
+    let (mut progress_offset, mut progress_lines) = (first_offset, 0);
+    loop {
+        let max_reached = max_count_reached(options, selected_lines);
+        if max_reached && context.after_remaining == 0 {
+            break;
+        }
+        if offset - progress_offset >= progress::STEP {
+            progress::add(offset - progress_offset, selected_lines - progress_lines);
+            (progress_offset, progress_lines) = (offset, selected_lines);
+        }
+        if let Some(prefilter) = prefilter {
+            let reader = input.as_reader();
+            if let Ok(buf) = reader.fill_buf() {
+                let skipped = prefilter.skippable(buf, terminator);
+                binary = binary || (detect_binary && memchr::memchr(0, &buf[..skipped]).is_some());
+                line_number += memchr::memchr_iter(terminator, &buf[..skipped]).count();
+                offset += skipped;
+                reader.consume(skipped);
+            }
+        }
+        let (record, bytes_read) = match input.read_record(terminator, &mut record_buffer, options.max_line_size) {
+            Ok((record, bytes_read, truncated)) => {
+                if let Some(limit) = options.max_line_size.filter(|_| truncated && !options.no_messages) {
+                    progress::clear_for_message();
+                    eprintln!(
+                        "note: {}: only the first {} bytes of the line at byte offset {} were searched",
+                        filename, limit, offset,
+                    );
+                }
+                (record, bytes_read)
+            }
+            Err(e) => {
+                warn(options, &format!("error reading {}: {}", filename, e));
+                break;
+            }
+        };
+        if bytes_read == 0 {
+            break;
+        }
+        binary = binary || (detect_binary && record.contains(&0));
+        // Bytes that aren't valid UTF-8 are matched and printed as U+FFFD, so
+        // a stray byte in a large log doesn't end the search. Offsets still
+        // count the original bytes.
+        let mut text = std::mem::take(&mut spare_text);
+        text.clear();
+        if options.hex {
+            // Each byte is its own character, as in Latin-1.
+            text.extend(record.iter().map(|&b| b as char));
+        } else {
+            text.push_str(&String::from_utf8_lossy(record));
+        }
+        let ending = trim_line_ending(options, &mut text);
+        line_number += 1;
+        let spans = multiline_spans.as_ref().map(|spans| {
+            let mut line_spans = Vec::new();
+            while let Some((_, span)) = spans.get(next_span).filter(|(index, _)| *index < line_number) {
+                line_spans.push(Span {
+                    start: span.start.min(text.len()),
+                    end: span.end.min(text.len()),
+                    first: span.first,
+                });
+                next_span += 1;
+            }
+            line_spans
         });
-        let is_match = match_line(options, &re, &line);
-        output_line(options, &re, &line, is_match);
+        let line = Line {
+            number: line_number,
+            offset,
+            text,
+            ending,
+            spans,
+        };
+        offset += bytes_read;
+
+        let is_match = match &line.spans {
+            Some(spans) => !spans.is_empty(),
+            None => match_line(options, matcher, &line.text),
+        };
+        let selected = is_selected(options, is_match) && !max_reached;
+        if stops_at_first_match(options) {
+            if selected {
+                selected_lines = 1;
+                break;
+            }
+            spare_text = line.text;
+            continue;
+        }
+        if selected && (options.json || options.stats) && !options.invert_match {
+            match_count += match &line.spans {
+                Some(spans) => spans.iter().filter(|span| span.first).count(),
+                None => matcher.find_iter(&line.text).filter(|m| !m.as_str().is_empty()).count(),
+            };
+        }
+        if options.count || options.count_matches {
+            if selected {
+                selected_count += count_line(options, matcher, &line);
+            }
+        } else if binary {
+            if selected {
+                if !options.json {
+                    output::print(format_args!("binary file {} matches\n", filename));
+                }
+                selected_lines += 1;
+                if let Some(chunk) = chunk.as_mut() {
+                    chunk.stopped = true;
+                }
+                break;
+            }
+        } else if !with_context {
+            if selected {
+                print_heading(options, filename, &mut heading_printed);
+                output_line(options, matcher, filename, &line, is_match);
+            }
+        } else if selected {
+            print_heading(options, filename, &mut heading_printed);
+            context.flush_before(options, matcher, filename);
+            context.mark_printed(options, line.number);
+            output_line(options, matcher, filename, &line, is_match);
+            context.after_remaining = options.after_context;
+        } else if context.after_remaining > 0 {
+            print_heading(options, filename, &mut heading_printed);
+            context.mark_printed(options, line.number);
+            context_output(options, matcher, filename, &line);
+            context.after_remaining -= 1;
+        } else if options.passthru {
+            print_heading(options, filename, &mut heading_printed);
+            context.mark_printed(options, line.number);
+            context_output(options, matcher, filename, &line);
+        } else {
+            if let Some(forgotten) = context.remember(options, line) {
+                spare_text = forgotten.text;
+            }
+            continue;
+        }
+        spare_text = line.text;
+        if selected {
+            selected_lines += 1;
+            if max_count_reached(options, selected_lines) && context.after_remaining == 0 {
+                break;
+            }
+        }
+    }
+
+    progress::add(offset - progress_offset, selected_lines - progress_lines);
+
+    let file_stats = Stats {
+        elapsed: start.elapsed(),
+        searches: 1,
+        searches_with_match: (selected_lines > 0) as usize,
+        bytes_searched: offset - first_offset,
+        bytes_printed: json::bytes_printed() - printed_before,
+        matched_lines: selected_lines,
+        matches: match_count,
+    };
+    if let Some(chunk) = chunk {
+        chunk.count = selected_count;
+        chunk.binary = binary;
+        return file_stats;
+    }
+    if heading_printed && options.json {
+        json::emit(&json::end(filename, &file_stats));
+    }
+    print_count(options, filename, selected_count);
+    file_stats
+}
+
+// Prints the -c or --count-matches count of a file.
+fn print_count(options: &MinigrepOptions, filename: &str, count: usize) {
+    if !options.count && !options.count_matches {
+        return;
+    }
+    if options.with_filename {
+        let path = linked_path(options, filename, None, None);
+        output::print(format_args!("{}{}{}\n", path, path_terminator(options, ":"), count));
+    } else {
+        output::print(format_args!("{}\n", count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    use super::{build_pattern, search_input, search_reader, Input, MinigrepOptions};
+    #[cfg(feature = "fancy")]
+    use crate::matcher::EngineChoice;
+    use crate::matcher::{Matcher, MatcherConfig};
+    use crate::output;
+
+    // What searching `input` prints.
+    fn search(options: &MinigrepOptions, query: &str, input: &str) -> String {
+        let config = MatcherConfig {
+            multi_line: options.multiline,
+            ..MatcherConfig::default()
+        };
+        let matcher = Matcher::new(&[build_pattern(options, query)], &config).unwrap();
+        let reader = Box::new(Cursor::new(input.as_bytes().to_vec()));
+        let (_, captured) = output::capture(|| search_reader(options, &matcher, "input", reader, Instant::now()));
+        captured.into_string()
+    }
+
+    // What searching `input` prints when it's read from a memory map.
+    fn search_mapped(options: &MinigrepOptions, query: &str, input: &str) -> String {
+        let config = MatcherConfig {
+            multi_line: options.multiline,
+            ..MatcherConfig::default()
+        };
+        let matcher = Matcher::new(&[build_pattern(options, query)], &config).unwrap();
+        let mapped = Input::Mapped(input.as_bytes());
+        let (_, captured) = output::capture(|| search_input(options, &matcher, "input", mapped, Instant::now(), None));
+        captured.into_string()
+    }
+
+    fn multiline() -> MinigrepOptions {
+        MinigrepOptions {
+            multiline: true,
+            threads: 1,
+            ..MinigrepOptions::default()
+        }
+    }
+
+    #[test]
+    fn multiline_only_matching_prints_each_line_of_a_match() {
+        let options = MinigrepOptions {
+            only_matching: true,
+            line_number: true,
+            ..multiline()
+        };
+        assert_eq!(search(&options, r"x\n\{", "ax\n{b\nx\n"), "1:x\n2:{\n");
+        assert_eq!(search(&options, r"b\nx", "ax\n{b\nx\n"), "2:b\n3:x\n");
+        assert_eq!(search(&options, r"x$", "ax\n{b\n"), "1:x\n");
+    }
+
+    #[test]
+    fn multiline_count_matches_counts_a_match_once() {
+        let options = MinigrepOptions {
+            count_matches: true,
+            ..multiline()
+        };
+        assert_eq!(search(&options, r"x\n\{", "ax\n{x\n{\n"), "2\n");
+        assert_eq!(search(&options, r"(?s)a.*b", "a\n\n\nb\n"), "1\n");
+        let options = MinigrepOptions {
+            count: true,
+            ..multiline()
+        };
+        assert_eq!(search(&options, r"x\n\{", "ax\n{x\n{\n"), "3\n");
+    }
+
+    #[test]
+    fn multiline_highlights_only_the_matched_parts() {
+        let options = MinigrepOptions {
+            color: true,
+            ..multiline()
+        };
+        let highlighted = search(&options, r"x\n\{", "xax\n{b\n");
+        let palette = crate::color::Palette::default();
+        let expected = format!(
+            "xa{}\n{}b\n",
+            palette.paint(crate::color::Style::Match, "x"),
+            palette.paint(crate::color::Style::Match, "{"),
+        );
+        assert_eq!(highlighted, expected);
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let options = MinigrepOptions {
+            threads: 1,
+            ..MinigrepOptions::default()
+        };
+        assert_eq!(search(&options, r"foo$", "a foo\r\nfoo bar\r\n"), "a foo\n");
+        let options = MinigrepOptions {
+            crlf: true,
+            ..options
+        };
+        assert_eq!(search(&options, r"foo$", "a foo\r\nfoo bar\r\n"), "a foo\r\n");
+        assert_eq!(search(&options, r"foo$", "a foo\nfoo bar\n"), "a foo\r\n");
+        let options = MinigrepOptions {
+            multiline: true,
+            ..options
+        };
+        assert_eq!(search(&options, r"foo$", "a foo\r\nfoo bar\r\n"), "a foo\r\n");
+    }
+
+    #[cfg(feature = "fancy")]
+    fn matches(options: &MinigrepOptions, query: &str, engine: EngineChoice, line: &str) -> Vec<(usize, usize)> {
+        let config = MatcherConfig {
+            engine,
+            ..MatcherConfig::default()
+        };
+        let matcher = Matcher::new(&[build_pattern(options, query)], &config).unwrap();
+        matcher.find_iter(line).map(|m| (m.start(), m.end())).collect()
+    }
+
+    #[test]
+    fn mapped_input_prints_what_read_input_does() {
+        let input = "\u{feff}one needle\r\ntwo\nthree needles and more\nfour\nneedle\u{0}five\nneedle six";
+        let cases = [
+            MinigrepOptions {
+                line_number: true,
+                byte_offset: true,
+                ..MinigrepOptions::default()
+            },
+            MinigrepOptions {
+                json: true,
+                ..MinigrepOptions::default()
+            },
+            MinigrepOptions {
+                text: true,
+                max_line_size: Some(8),
+                ..MinigrepOptions::default()
+            },
+            MinigrepOptions {
+                only_matching: true,
+                line_number: true,
+                ..multiline()
+            },
+        ];
+        let elapsed = regex::Regex::new(r#""elapsed":\{[^}]*\}"#).unwrap();
+        for options in &cases {
+            for query in ["needle", r"needles?\s+\w+", r"e\n[tf]", "x"] {
+                let read = search(options, query, input);
+                let mapped = search_mapped(options, query, input);
+                assert_eq!(elapsed.replace_all(&mapped, ""), elapsed.replace_all(&read, ""), "{}", query);
+            }
+        }
+    }
+
+    // A mapped file isn't copied to be matched with -U, so --max-memory
+    // doesn't rule it out.
+    #[test]
+    fn mapped_multiline_input_isnt_read_whole() {
+        let options = MinigrepOptions {
+            max_memory: Some(16),
+            line_number: true,
+            ..multiline()
+        };
+        let input = format!("{}\nneedle\nhay\n", "hay ".repeat(100));
+        assert_eq!(search(&options, r"needle\nhay", &input), "");
+        assert_eq!(search_mapped(&options, r"needle\nhay", &input), "2:needle\n3:hay\n");
+    }
+
+    // A pattern the default engine rejects is handed to the fancy engine as -w
+    // wrapped it, and means the same as it would with --engine fancy.
+    #[cfg(feature = "fancy")]
+    #[test]
+    fn auto_fallback_matches_fancy_engine_with_word_regexp() {
+        let options = MinigrepOptions {
+            word_regexp: true,
+            ..MinigrepOptions::default()
+        };
+        let line = "aa a-aa bb -x (x) b b_b bb-b";
+        for query in [r"(\w)\1", r"(?<=-)\w+", r"b(?!b)", r"-x", r"\(x\)", r"b+", r"(\w+)-\1"] {
+            let fancy = matches(&options, query, EngineChoice::Fancy, line);
+            assert_eq!(matches(&options, query, EngineChoice::Auto, line), fancy, "{}", query);
+            if regex::Regex::new(&build_pattern(&options, query)).is_ok() {
+                assert_eq!(matches(&options, query, EngineChoice::Default, line), fancy, "{}", query);
+            }
+        }
+    }
+
+    #[cfg(feature = "fancy")]
+    #[test]
+    fn auto_fallback_survives_long_lines() {
+        let options = MinigrepOptions::default();
+        let line = format!("{}aaneedle", "ab".repeat(100_000));
+        assert_eq!(matches(&options, r"(a+)\1needle", EngineChoice::Auto, &line), vec![(200_000, line.len())]);
+        let line = "ab".repeat(100_000);
+        assert!(matches(&options, r"(a+)\1needle", EngineChoice::Auto, &line).is_empty());
     }
 }
@@ -0,0 +1,525 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use regex_syntax::hir::{Capture, Class, ClassBytes, ClassUnicode, ClassUnicodeRange, Hir, HirKind, Repetition};
+
+#[cfg(feature = "fancy")]
+use crate::fancy;
+use crate::prefilter::Prefilter;
+
+// A match of one of the patterns in a line. This mirrors regex::Match so that
+// matches from either engine look the same to the printers.
+#[derive(Clone, Copy)]
+pub struct Match<'h> {
+    haystack: &'h str,
+    start: usize,
+    end: usize,
+}
+
+impl<'h> Match<'h> {
+    fn new(haystack: &'h str, start: usize, end: usize) -> Match<'h> {
+        Match { haystack, start, end }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn as_str(&self) -> &'h str {
+        &self.haystack[self.start..self.end]
+    }
+}
+
+// The spans of a pattern's capture groups in a line; group 0 is the whole match.
+pub struct Captures<'h> {
+    haystack: &'h str,
+    groups: Vec<Option<(usize, usize)>>,
+}
+
+impl<'h> Captures<'h> {
+    fn from_regex(haystack: &'h str, captures: regex::Captures<'h>) -> Captures<'h> {
+        let groups = captures.iter()
+            .map(|group| group.map(|m| (m.start(), m.end())))
+            .collect();
+        Captures { haystack, groups }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Match<'h>> {
+        let (start, end) = self.groups.get(index).copied().flatten()?;
+        Some(Match::new(self.haystack, start, end))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match<'h>>> + '_ {
+        (0..self.groups.len()).map(move |index| self.get(index))
+    }
+
+    // Expands $1, ${1}, $name and ${name} in a --replace template, with $$ for
+    // a literal dollar sign. Like the regex crate, a reference to a group that
+    // doesn't exist or didn't match expands to nothing, and $name takes the
+    // longest run of word characters as the name.
+    fn expand(&self, template: &str, names: &[Option<String>], dst: &mut String) {
+        let mut rest = template;
+        while let Some(dollar) = rest.find('$') {
+            dst.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                dst.push('$');
+                rest = after;
+                continue;
+            }
+            let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(close) => (&braced[..close], &braced[close + 1..]),
+                    None => {
+                        dst.push('$');
+                        continue;
+                    }
+                }
+            } else {
+                let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                if end == 0 {
+                    dst.push('$');
+                    continue;
+                }
+                (&rest[..end], &rest[end..])
+            };
+            rest = after;
+            let index = name.parse::<usize>().ok()
+                .or_else(|| names.iter().position(|n| n.as_deref() == Some(name)));
+            if let Some(group) = index.and_then(|index| self.get(index)) {
+                dst.push_str(group.as_str());
+            }
+        }
+        dst.push_str(rest);
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum EngineChoice {
+    Default,
+    Fancy,
+    // Uses the regex crate unless a pattern needs syntax only the fancy engine
+    // supports, such as lookaround or backreferences.
+    #[default]
+    Auto,
+}
+
+impl EngineChoice {
+    pub fn parse(value: &str) -> Result<EngineChoice, String> {
+        match value {
+            "default" => Ok(EngineChoice::Default),
+            "fancy" => Ok(EngineChoice::Fancy),
+            "auto" => Ok(EngineChoice::Auto),
+            _ => Err(format!("invalid value for --engine: {} (expected default, fancy or auto)", value)),
+        }
+    }
+}
+
+// Settings that apply to every pattern of a Matcher.
+#[derive(Default)]
+pub struct MatcherConfig {
+    pub case_insensitive: bool,
+    pub overlapping: bool,
+    // ^ and $ match at line boundaries inside the searched text, for -U.
+    pub multi_line: bool,
+    // . also matches line terminators, for --multiline-dotall.
+    pub dot_all: bool,
+    pub engine: EngineChoice,
+    // \w, \d, \s, \b and case folding only know about ASCII, for --no-unicode.
+    pub no_unicode: bool,
+    // Limits in bytes on the compiled program and on the lazy DFA's cache;
+    // None keeps the regex crate's defaults.
+    pub size_limit: Option<usize>,
+    pub dfa_size_limit: Option<usize>,
+}
+
+// Without Unicode the regex crate's classes like . or [^a] match any byte, which
+// a &str regex rejects, so --no-unicode patterns are parsed in ASCII mode and
+// every class that includes non-ASCII bytes is widened to all non-ASCII
+// characters. The rewritten pattern already has the flags applied.
+fn ascii_pattern(pattern: &str, config: &MatcherConfig) -> Result<String, String> {
+    let hir = regex_syntax::ParserBuilder::new()
+        .unicode(false)
+        .utf8(false)
+        .case_insensitive(config.case_insensitive)
+        .multi_line(config.multi_line)
+        .crlf(config.multi_line)
+        .dot_matches_new_line(config.dot_all)
+        .build()
+        .parse(pattern)
+        .map_err(|e| format!("error parsing pattern {}: {}", pattern, e))?;
+    Ok(widen_byte_classes(hir).to_string())
+}
+
+fn widen_byte_class(bytes: &ClassBytes) -> ClassUnicode {
+    let mut class = ClassUnicode::empty();
+    for range in bytes.iter() {
+        if range.start() <= 0x7f {
+            class.push(ClassUnicodeRange::new(range.start() as char, range.end().min(0x7f) as char));
+        }
+        if range.end() > 0x7f {
+            class.push(ClassUnicodeRange::new('\u{80}', char::MAX));
+        }
+    }
+    class
+}
+
+fn widen_byte_classes(hir: Hir) -> Hir {
+    match hir.into_kind() {
+        HirKind::Empty => Hir::empty(),
+        HirKind::Literal(literal) => Hir::literal(literal.0),
+        HirKind::Class(Class::Bytes(bytes)) => Hir::class(Class::Unicode(widen_byte_class(&bytes))),
+        HirKind::Class(class) => Hir::class(class),
+        HirKind::Look(look) => Hir::look(look),
+        HirKind::Repetition(repetition) => Hir::repetition(Repetition {
+            sub: Box::new(widen_byte_classes(*repetition.sub)),
+            ..repetition
+        }),
+        HirKind::Capture(capture) => Hir::capture(Capture {
+            sub: Box::new(widen_byte_classes(*capture.sub)),
+            ..capture
+        }),
+        HirKind::Concat(subs) => Hir::concat(subs.into_iter().map(widen_byte_classes).collect()),
+        HirKind::Alternation(subs) => Hir::alternation(subs.into_iter().map(widen_byte_classes).collect()),
+    }
+}
+
+fn build_regex(pattern: &str, config: &MatcherConfig) -> Result<Regex, regex::Error> {
+    let mut builder = RegexBuilder::new(pattern);
+    builder
+        .case_insensitive(config.case_insensitive && !config.no_unicode)
+        .multi_line(config.multi_line)
+        .crlf(config.multi_line)
+        .dot_matches_new_line(config.dot_all);
+    if let Some(limit) = config.size_limit {
+        builder.size_limit(limit);
+    }
+    if let Some(limit) = config.dfa_size_limit {
+        builder.dfa_size_limit(limit);
+    }
+    builder.build()
+}
+
+#[derive(Clone)]
+enum Engine {
+    // The set answers "does any pattern match" in a single pass; spans come
+    // from one combined alternation so that a line with several hits is
+    // scanned leftmost-first across all patterns.
+    Regex {
+        set: RegexSet,
+        regexes: Vec<Regex>,
+        combined: Regex,
+        prefilter: Option<Prefilter>,
+    },
+    // Backreferences are numbered per pattern, so the patterns can't be joined
+    // into one alternation; each is tried in turn instead.
+    #[cfg(feature = "fancy")]
+    Fancy(Vec<fancy::Regex>),
+}
+
+// Matches a line against any of several patterns.
+#[derive(Clone)]
+pub struct Matcher {
+    engine: Engine,
+    // Group names of each pattern, indexed by group number.
+    names: Vec<Vec<Option<String>>>,
+    overlapping: bool,
+}
+
+// The part of a line covered by a match that was found in the whole input,
+// for -U. Offsets are into the line, and `first` is whether the match starts
+// in this line rather than in an earlier one.
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub first: bool,
+}
+
+// Iterates over the matches in a line. Overlapping iteration restarts the search
+// one character after the start of each match instead of at its end, so "aa"
+// is found three times in "aaaa".
+pub enum MatchIter<'r, 'h> {
+    Leftmost(regex::Matches<'r, 'h>, &'h str),
+    Stepping {
+        matcher: &'r Matcher,
+        line: &'h str,
+        start: usize,
+        last_end: Option<usize>,
+    },
+    Spans(std::slice::Iter<'h, Span>, &'h str),
+}
+
+impl<'h> MatchIter<'_, 'h> {
+    // The matches of a line that were found beforehand.
+    pub fn from_spans(line: &'h str, spans: &'h [Span]) -> Self {
+        MatchIter::Spans(spans.iter(), line)
+    }
+}
+
+impl<'h> Iterator for MatchIter<'_, 'h> {
+    type Item = Match<'h>;
+
+    fn next(&mut self) -> Option<Match<'h>> {
+        let (matcher, line, start, last_end) = match self {
+            MatchIter::Leftmost(matches, line) => {
+                return matches.next().map(|m| Match::new(line, m.start(), m.end()));
+            }
+            MatchIter::Spans(spans, line) => {
+                return spans.next().map(|span| Match::new(line, span.start, span.end));
+            }
+            MatchIter::Stepping { matcher, line, start, last_end } => (*matcher, *line, start, last_end),
+        };
+        loop {
+            if *start > line.len() {
+                return None;
+            }
+            let m = matcher.find_at(line, *start)?;
+            let width = line[m.start()..].chars().next().map_or(1, char::len_utf8);
+            if matcher.overlapping {
+                *start = m.start() + width;
+                return Some(m);
+            }
+            // As in the regex crate, an empty match right where the previous
+            // match ended is skipped.
+            if m.start() == m.end() && *last_end == Some(m.start()) {
+                *start = m.start() + width;
+                continue;
+            }
+            *start = m.end();
+            *last_end = Some(m.end());
+            return Some(m);
+        }
+    }
+}
+
+fn regex_engine(patterns: &[String], config: &MatcherConfig) -> Result<Engine, String> {
+    let ascii_patterns;
+    let patterns = if config.no_unicode {
+        ascii_patterns = patterns.iter()
+            .map(|pattern| ascii_pattern(pattern, config))
+            .collect::<Result<Vec<String>, String>>()?;
+        &ascii_patterns
+    } else {
+        patterns
+    };
+    let mut regexes = Vec::new();
+    for pattern in patterns {
+        let re = build_regex(pattern, config)
+            .map_err(|e| format!("error parsing pattern {}: {}", pattern, e))?;
+        regexes.push(re);
+    }
+
+    let mut set = RegexSetBuilder::new(patterns);
+    set.case_insensitive(config.case_insensitive && !config.no_unicode)
+        .multi_line(config.multi_line)
+        .crlf(config.multi_line)
+        .dot_matches_new_line(config.dot_all);
+    if let Some(limit) = config.size_limit {
+        set.size_limit(limit);
+    }
+    if let Some(limit) = config.dfa_size_limit {
+        set.dfa_size_limit(limit);
+    }
+    let set = set.build().map_err(|e| format!("error parsing patterns: {}", e))?;
+
+    let combined = if regexes.is_empty() {
+        Regex::new(r"[^\s\S]").unwrap()
+    } else if regexes.len() == 1 {
+        regexes[0].clone()
+    } else {
+        let alternation: Vec<String> = patterns.iter()
+            .map(|p| format!("(?:{})", p))
+            .collect();
+        build_regex(&alternation.join("|"), config)
+            .map_err(|e| format!("error parsing patterns: {}", e))?
+    };
+    let prefilter = Prefilter::new(patterns, |pattern| {
+        regex_syntax::ParserBuilder::new()
+            .case_insensitive(config.case_insensitive && !config.no_unicode)
+            .multi_line(config.multi_line)
+            .crlf(config.multi_line)
+            .dot_matches_new_line(config.dot_all)
+            .build()
+            .parse(pattern)
+            .ok()
+    });
+    Ok(Engine::Regex { set, regexes, combined, prefilter })
+}
+
+#[cfg(feature = "fancy")]
+fn fancy_engine(patterns: &[String], config: &MatcherConfig) -> Result<Engine, String> {
+    let flags = fancy::Flags {
+        case_insensitive: config.case_insensitive,
+        multi_line: config.multi_line,
+        dot_all: config.dot_all,
+        no_unicode: config.no_unicode,
+    };
+    let mut regexes = Vec::new();
+    for pattern in patterns {
+        let re = fancy::Regex::new(pattern, &flags)
+            .map_err(|e| format!("error parsing pattern {}: {}", pattern, e))?;
+        regexes.push(re);
+    }
+    Ok(Engine::Fancy(regexes))
+}
+
+#[cfg(not(feature = "fancy"))]
+fn fancy_engine(_patterns: &[String], _config: &MatcherConfig) -> Result<Engine, String> {
+    Err("the fancy regex engine isn't available: minigrep was built without the \"fancy\" feature".to_string())
+}
+
+impl Matcher {
+    pub fn new(patterns: &[String], config: &MatcherConfig) -> Result<Matcher, String> {
+        let engine = match config.engine {
+            EngineChoice::Default => regex_engine(patterns, config)?,
+            EngineChoice::Fancy => fancy_engine(patterns, config)?,
+            // The fancy engine keeps its backtracking on the heap, so however
+            // long a line is it only costs time, never the process.
+            EngineChoice::Auto => regex_engine(patterns, config).or_else(|e| {
+                if e.contains("not supported") {
+                    fancy_engine(patterns, config).map_err(|_| e)
+                } else {
+                    Err(e)
+                }
+            })?,
+        };
+        let names = match &engine {
+            Engine::Regex { regexes, .. } => regexes.iter()
+                .map(|re| re.capture_names().map(|name| name.map(str::to_string)).collect())
+                .collect(),
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(regexes) => regexes.iter().map(|re| re.names().to_vec()).collect(),
+        };
+        Ok(Matcher {
+            engine,
+            names,
+            overlapping: config.overlapping,
+        })
+    }
+
+    pub fn engine_name(&self) -> &'static str {
+        match &self.engine {
+            Engine::Regex { .. } => "default",
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(_) => "fancy",
+        }
+    }
+
+    // The fancy engine's patterns aren't understood well enough to have one.
+    pub fn prefilter(&self) -> Option<&Prefilter> {
+        match &self.engine {
+            Engine::Regex { prefilter, .. } => prefilter.as_ref(),
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(_) => None,
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match &self.engine {
+            Engine::Regex { set, .. } => set.is_match(line),
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(regexes) => regexes.iter().any(|re| re.captures_at(line, 0).is_some()),
+        }
+    }
+
+    // Returns the leftmost match at or after `start`. Between patterns that
+    // match at the same position, the first one wins.
+    fn find_at<'h>(&self, line: &'h str, start: usize) -> Option<Match<'h>> {
+        match &self.engine {
+            Engine::Regex { combined, .. } => combined.find_at(line, start)
+                .map(|m| Match::new(line, m.start(), m.end())),
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(regexes) => regexes.iter()
+                .filter_map(|re| {
+                    let (s, e) = re.captures_at(line, start)?[0]?;
+                    Some(Match::new(line, s, e))
+                })
+                .min_by_key(|m| m.start()),
+        }
+    }
+
+    pub fn find<'h>(&self, line: &'h str) -> Option<Match<'h>> {
+        self.find_at(line, 0)
+    }
+
+    pub fn find_iter<'r, 'h>(&'r self, line: &'h str) -> MatchIter<'r, 'h> {
+        match &self.engine {
+            Engine::Regex { combined, .. } if !self.overlapping => MatchIter::Leftmost(combined.find_iter(line), line),
+            _ => MatchIter::Stepping {
+                matcher: self,
+                line,
+                start: 0,
+                last_end: None,
+            },
+        }
+    }
+
+    // The captures of the pattern that produced `m`, found by rerunning each
+    // pattern at the start of the match.
+    fn captures_for<'h>(&self, line: &'h str, m: &Match<'h>) -> Option<(usize, Captures<'h>)> {
+        (0..self.names.len())
+            .filter_map(|index| Some((index, self.captures_at(index, line, m.start())?)))
+            .find(|(_, c)| c.get(0).is_some_and(|whole| whole.range() == m.range()))
+    }
+
+    fn captures_at<'h>(&self, index: usize, line: &'h str, start: usize) -> Option<Captures<'h>> {
+        match &self.engine {
+            Engine::Regex { regexes, .. } => regexes[index].captures_at(line, start)
+                .map(|captures| Captures::from_regex(line, captures)),
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(regexes) => regexes[index].captures_at(line, start)
+                .map(|groups| Captures { haystack: line, groups }),
+        }
+    }
+
+    // Pairs each leftmost match with its replacement, expanding $1 and $name
+    // references against the groups of whichever pattern produced the match.
+    pub fn replacements<'h>(&self, line: &'h str, template: &str) -> Vec<(Match<'h>, String)> {
+        let leftmost = match &self.engine {
+            Engine::Regex { combined, .. } => MatchIter::Leftmost(combined.find_iter(line), line),
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(_) => MatchIter::Stepping {
+                matcher: self,
+                line,
+                start: 0,
+                last_end: None,
+            },
+        };
+        leftmost
+            .map(|m| {
+                let mut replacement = String::new();
+                match self.captures_for(line, &m) {
+                    Some((index, captures)) => captures.expand(template, &self.names[index], &mut replacement),
+                    None => replacement.push_str(m.as_str()),
+                }
+                (m, replacement)
+            })
+            .collect()
+    }
+
+    // Capture groups are numbered per pattern, so they come from the first
+    // pattern that matches rather than from the combined alternation. The group
+    // names of that pattern are returned alongside.
+    pub fn captures<'r, 'h>(&'r self, line: &'h str) -> Option<(Captures<'h>, Vec<Option<&'r str>>)> {
+        let (index, captures) = match &self.engine {
+            Engine::Regex { set, .. } => {
+                let index = set.matches(line).into_iter().next()?;
+                (index, self.captures_at(index, line, 0)?)
+            }
+            #[cfg(feature = "fancy")]
+            Engine::Fancy(_) => (0..self.names.len())
+                .find_map(|index| Some((index, self.captures_at(index, line, 0)?)))?,
+        };
+        let names = self.names[index].iter().map(|name| name.as_deref()).collect();
+        Some((captures, names))
+    }
+}
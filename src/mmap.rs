@@ -0,0 +1,69 @@
+// A read-only memory map of a whole file, for --mmap. Mapping needs mmap(2),
+// so elsewhere `open` always fails and files are read as usual. If the file
+// shrinks while it's mapped, reading past its new end kills the process with
+// SIGBUS, which is why mapping isn't the default.
+pub struct Mmap {
+    address: *mut u8,
+    len: usize,
+}
+
+// The mapping is never written to, so it can be read from any thread.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(address: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(address: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+impl Mmap {
+    // Maps `filename`, or returns None if it isn't a regular file or can't be
+    // mapped, such as when it's empty.
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    pub fn open(filename: &str) -> Option<Mmap> {
+        use std::fs::File;
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(filename).ok()?;
+        let metadata = file.metadata().ok()?;
+        let len = metadata.len() as usize;
+        if !metadata.is_file() || len == 0 {
+            return None;
+        }
+        // The mapping stays valid after the file is closed.
+        let address = unsafe {
+            sys::mmap(std::ptr::null_mut(), len, sys::PROT_READ, sys::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if address == sys::MAP_FAILED {
+            return None;
+        }
+        Some(Mmap { address: address as *mut u8, len })
+    }
+
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    pub fn open(_filename: &str) -> Option<Mmap> {
+        None
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.address, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(all(unix, target_pointer_width = "64"))]
+        unsafe {
+            sys::munmap(self.address as *mut std::os::raw::c_void, self.len);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::io::{self, BufRead};
+use std::process::{Command, Stdio};
+
+use crate::process;
+
+// http:// and https:// arguments are fetched with curl, which handles TLS,
+// proxies and redirects, and streamed through the search as they arrive.
+
+const MAX_REDIRECTS: u32 = 10;
+const CONNECT_TIMEOUT_SECS: u32 = 10;
+// A download is given up on when it stalls, rather than after a fixed time,
+// so large bodies can still be searched over slow links.
+const STALL_TIMEOUT_SECS: u32 = 30;
+
+pub fn open(url: &str) -> io::Result<Box<dyn BufRead>> {
+    let mut curl = Command::new("curl");
+    curl.args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--proto", "=http,https", "--proto-redir", "=http,https"])
+        .args(["--max-redirs", &MAX_REDIRECTS.to_string()])
+        .args(["--connect-timeout", &CONNECT_TIMEOUT_SECS.to_string()])
+        .args(["--speed-limit", "1", "--speed-time", &STALL_TIMEOUT_SECS.to_string()])
+        .arg("--")
+        .arg(url)
+        .stdin(Stdio::null());
+    process::spawn_reader(curl, "curl")
+}
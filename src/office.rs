@@ -0,0 +1,237 @@
+use crate::archive;
+
+// Text extraction for Word, PowerPoint and Excel documents, which are zip
+// archives of XML parts. Only the text is kept: paragraphs and spreadsheet
+// rows become lines, and spreadsheet cells are separated by tabs.
+
+const EXTENSIONS: &[&str] = &["docx", "docm", "pptx", "pptm", "xlsx", "xlsm"];
+
+pub fn is_document(filename: &str) -> bool {
+    let lowercase = filename.to_ascii_lowercase();
+    EXTENSIONS.iter().any(|extension| lowercase.rsplit('.').next() == Some(extension))
+}
+
+enum Token<'a> {
+    Open { name: &'a str, attributes: &'a str, empty: bool },
+    Close(&'a str),
+    Text(&'a str),
+}
+
+// Splits XML into tags and text, dropping the declaration, comments and
+// processing instructions. Names lose their namespace prefix, so w:t is t.
+fn tokens(xml: &str) -> Vec<Token<'_>> {
+    let local = |name: &'_ str| -> usize { name.find(':').map_or(0, |i| i + 1) };
+    let mut tokens = Vec::new();
+    let mut rest = xml;
+    while !rest.is_empty() {
+        let Some(open) = rest.find('<') else {
+            tokens.push(Token::Text(rest));
+            break;
+        };
+        if open > 0 {
+            tokens.push(Token::Text(&rest[..open]));
+        }
+        rest = &rest[open..];
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            tokens.push(Token::Text(&cdata[..end]));
+            rest = cdata.get(end + 3..).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[1..end];
+        rest = rest.get(end + 1..).unwrap_or("");
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            tokens.push(Token::Close(&name[local(name)..]));
+            continue;
+        }
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let name = &tag[..name_end];
+        tokens.push(Token::Open {
+            name: &name[local(name)..],
+            attributes: &tag[name_end..],
+            empty,
+        });
+    }
+    tokens
+}
+
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next()?;
+        let value = &value[1..];
+        let end = value.find(quote)?;
+        if key == name {
+            return Some(&value[..end]);
+        }
+        rest = &value[end + 1..];
+    }
+    None
+}
+
+fn unescape(text: &str, out: &mut String) {
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semicolon) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semicolon];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|decimal| decimal.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semicolon + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+// Word and PowerPoint keep text in <w:t> and <a:t> runs inside paragraphs.
+fn paragraph_text(xml: &str, out: &mut String) {
+    let mut in_text = false;
+    for token in tokens(xml) {
+        match token {
+            Token::Open { name: "t", empty: false, .. } => in_text = true,
+            Token::Open { name: "tab", .. } => out.push('\t'),
+            Token::Open { name: "br", .. } | Token::Open { name: "cr", .. } => out.push('\n'),
+            Token::Close("t") => in_text = false,
+            Token::Close("p") => out.push('\n'),
+            Token::Text(text) if in_text => unescape(text, out),
+            _ => {}
+        }
+    }
+}
+
+// Each <si> item of xl/sharedStrings.xml, which cells of type "s" refer to
+// by index.
+fn shared_strings(xml: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut in_text = false;
+    for token in tokens(xml) {
+        match token {
+            Token::Open { name: "si", .. } => strings.push(String::new()),
+            Token::Open { name: "t", empty: false, .. } => in_text = true,
+            Token::Close("t") => in_text = false,
+            Token::Text(text) if in_text => {
+                if let Some(string) = strings.last_mut() {
+                    unescape(text, string);
+                }
+            }
+            _ => {}
+        }
+    }
+    strings
+}
+
+fn sheet_text(xml: &str, strings: &[String], out: &mut String) {
+    let mut row: Vec<String> = Vec::new();
+    let mut cell_type = "";
+    let mut value = String::new();
+    let mut in_value = false;
+    for token in tokens(xml) {
+        match token {
+            Token::Open { name: "row", .. } => row.clear(),
+            Token::Open { name: "c", attributes, .. } => {
+                cell_type = attribute(attributes, "t").unwrap_or("");
+                value.clear();
+            }
+            Token::Open { name: "v", empty: false, .. } | Token::Open { name: "t", empty: false, .. } => in_value = true,
+            Token::Close("v") | Token::Close("t") => in_value = false,
+            Token::Text(text) if in_value => unescape(text, &mut value),
+            Token::Close("c") => {
+                let text = match cell_type {
+                    "s" => value.trim().parse::<usize>().ok()
+                        .and_then(|index| strings.get(index))
+                        .cloned()
+                        .unwrap_or_default(),
+                    _ => value.clone(),
+                };
+                row.push(text);
+            }
+            Token::Close("row") => {
+                out.push_str(&row.join("\t"));
+                out.push('\n');
+            }
+            _ => {}
+        }
+    }
+}
+
+// The number in a part name like ppt/slides/slide12.xml, so parts can be put
+// in document order.
+fn part_number(name: &str) -> usize {
+    let digits: String = name.chars().filter(char::is_ascii_digit).collect();
+    digits.parse().unwrap_or(0)
+}
+
+pub fn text(filename: &str) -> Result<String, String> {
+    let is_part = |name: &str| {
+        name == "word/document.xml"
+            || (name.starts_with("ppt/slides/slide") && name.ends_with(".xml"))
+            || name == "xl/sharedStrings.xml"
+            || (name.starts_with("xl/worksheets/sheet") && name.ends_with(".xml"))
+    };
+    let mut parts = Vec::new();
+    let mut error = None;
+    archive::read_zip(filename, &is_part, None, &mut |name, contents| match contents {
+        Ok(contents) => {
+            parts.push((name.to_string(), String::from_utf8_lossy(&contents).into_owned()));
+            true
+        }
+        Err(e) => {
+            error = Some(format!("{}: {}", name, e));
+            false
+        }
+    })?;
+    if let Some(error) = error {
+        return Err(error);
+    }
+    parts.sort_by_key(|(name, _)| part_number(name));
+
+    let strings = parts.iter()
+        .find(|(name, _)| name == "xl/sharedStrings.xml")
+        .map(|(_, xml)| shared_strings(xml))
+        .unwrap_or_default();
+    let mut out = String::new();
+    for (name, xml) in &parts {
+        if name.starts_with("xl/worksheets/") {
+            sheet_text(xml, &strings, &mut out);
+        } else if name != "xl/sharedStrings.xml" {
+            paragraph_text(xml, &mut out);
+        }
+    }
+    Ok(out)
+}
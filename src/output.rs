@@ -0,0 +1,223 @@
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Stdout, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::progress;
+
+// Everything minigrep prints for a search goes through here. It normally goes
+// straight to standard output, but a thread searching files in parallel
+// captures the output of each file, to be printed whole by the one thread
+// that writes to standard output, so lines of different files never mix.
+// Standard output is flushed after every line unless block buffering is
+// turned on, as it is by default when it isn't a terminal.
+
+// How much is written to standard output at a time when block buffered.
+const BLOCK_SIZE: usize = 64 << 10;
+
+// What was printed for one file while capturing. Once the output held by
+// every capture reaches the limit set by --max-memory, the rest of it goes to
+// a temporary file instead.
+#[derive(Default)]
+pub struct Capture {
+    text: Vec<u8>,
+    spill: Option<Spill>,
+    // Whether the output starts a new group under --heading, which needs a
+    // blank line before it unless it's the first.
+    heading: bool,
+}
+
+struct Spill {
+    file: File,
+    // Where the file is to be removed from once it's closed; on Unix it's
+    // removed as soon as it's open, so it can't be left behind.
+    path: Option<PathBuf>,
+}
+
+static CAPTURED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static CAPTURED_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn limit_captured(limit: usize) {
+    CAPTURED_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+impl Spill {
+    fn create() -> io::Result<Spill> {
+        let name = format!("minigrep-{}-{}", process::id(), SPILLS.fetch_add(1, Ordering::Relaxed));
+        let path = env::temp_dir().join(name);
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        let path = if cfg!(unix) && fs::remove_file(&path).is_ok() { None } else { Some(path) };
+        Ok(Spill { file, path })
+    }
+}
+
+impl Capture {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.spill.is_none() && !self.heading
+    }
+
+    // Writes out the captured output: what's held in memory, then what was
+    // spilled after it.
+    fn write_to(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.text)?;
+        if let Some(spill) = self.spill.as_mut() {
+            spill.file.seek(SeekFrom::Start(0))?;
+            io::copy(&mut spill.file, out)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Capture {
+    pub fn into_string(mut self) -> String {
+        let mut out = Vec::new();
+        self.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let held = CAPTURED_BYTES.load(Ordering::Relaxed);
+        if self.spill.is_none() && held.saturating_add(buf.len()) > CAPTURED_LIMIT.load(Ordering::Relaxed) {
+            // If no file can be made, the output is held in memory after all.
+            self.spill = Spill::create().ok();
+        }
+        match self.spill.as_mut() {
+            Some(spill) => spill.file.write(buf),
+            None => {
+                self.text.extend_from_slice(buf);
+                CAPTURED_BYTES.fetch_add(buf.len(), Ordering::Relaxed);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        CAPTURED_BYTES.fetch_sub(self.text.len(), Ordering::Relaxed);
+        if let Some(Spill { file, path }) = self.spill.take() {
+            drop(file);
+            if let Some(path) = path {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+static PRINTED_HEADING: AtomicBool = AtomicBool::new(false);
+
+// Set by `block_buffered`; while it's None, writes go to the standard library's
+// own line-buffered standard output.
+static BLOCK_BUFFER: Mutex<Option<BufWriter<Stdout>>> = Mutex::new(None);
+
+pub fn block_buffered() {
+    *BLOCK_BUFFER.lock().unwrap_or_else(|e| e.into_inner()) = Some(BufWriter::with_capacity(BLOCK_SIZE, io::stdout()));
+}
+
+// Writes out whatever is still buffered, returning the error if the output
+// couldn't be written.
+pub fn flush() -> io::Result<()> {
+    match BLOCK_BUFFER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(buffer) => buffer.flush(),
+        None => io::stdout().flush(),
+    }
+}
+
+// Whether what's been written to the line-buffered standard output ends a
+// line, so --progress can be drawn below it.
+static AT_LINE_START: AtomicBool = AtomicBool::new(true);
+
+pub fn at_line_start() -> bool {
+    AT_LINE_START.load(Ordering::Relaxed)
+}
+
+struct LineTracker<W>(W);
+
+impl<W: Write> Write for LineTracker<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.0.write(buf)?;
+        if let Some(&last) = buf[..written].last() {
+            AT_LINE_START.store(last == b'\n', Ordering::Relaxed);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// Errors are left for `flush` to report, since a buffered write may only
+// fail once it's flushed anyway.
+fn write_stdout(f: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+    let _ = match BLOCK_BUFFER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(buffer) => f(buffer),
+        None => {
+            let mut stdout = LineTracker(io::stdout().lock());
+            progress::clear_for_output();
+            f(&mut stdout)
+        }
+    };
+}
+
+pub fn print(args: fmt::Arguments) {
+    CAPTURE.with(|capture| match capture.borrow_mut().as_mut() {
+        Some(capture) => {
+            let _ = capture.write_fmt(args);
+        }
+        None => write_stdout(|out| out.write_fmt(args)),
+    });
+}
+
+// Called before printing a --heading file name, to separate it from the
+// previous file's group by a blank line.
+pub fn heading_break() {
+    CAPTURE.with(|capture| match capture.borrow_mut().as_mut() {
+        Some(capture) => capture.heading = true,
+        None => {
+            if PRINTED_HEADING.swap(true, Ordering::Relaxed) {
+                write_stdout(|out| out.write_all(b"\n"));
+            }
+        }
+    });
+}
+
+// Runs `f`, capturing what it prints instead of printing it. Inside another
+// capture, what `f` prints is returned rather than added to the outer one.
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Capture) {
+    let outer = CAPTURE.with(|capture| capture.replace(Some(Capture::default())));
+    let result = f();
+    let captured = CAPTURE.with(|capture| capture.replace(outer)).unwrap_or_default();
+    (result, captured)
+}
+
+// Prints what `capture` captured, or adds it to what this thread is
+// capturing itself.
+pub fn write_captured(mut captured: Capture) {
+    if captured.heading {
+        heading_break();
+    }
+    CAPTURE.with(|capture| match capture.borrow_mut().as_mut() {
+        Some(capture) => {
+            let _ = captured.write_to(capture);
+        }
+        None => write_stdout(|out| captured.write_to(out)),
+    });
+}
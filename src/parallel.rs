@@ -0,0 +1,98 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::matcher::Matcher;
+use crate::output::{self, Capture};
+use crate::stats::Stats;
+use crate::{for_each_file, search_and_report, MinigrepOptions};
+
+// How many found files may wait for a free thread, per thread, before the
+// walk waits too.
+const QUEUE_PER_THREAD: usize = 4;
+
+thread_local! {
+    static IN_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+// Whether this thread is one of those searching files in parallel, which
+// mustn't start threads of their own to search a file in pieces, or there
+// could be as many as the square of --threads.
+pub fn in_worker() -> bool {
+    IN_WORKER.with(Cell::get)
+}
+
+// Searches the files named by the arguments on `options.threads` threads,
+// each with its own copy of the matcher. The walk runs on this thread and
+// hands out files as it finds them; each file's output is captured by the
+// thread that searched it and printed whole by a single writer thread, in
+// the order the files were found unless --no-sort is given.
+pub fn search_files(options: &MinigrepOptions, matcher: &Matcher) -> Stats {
+    let threads = options.threads;
+    // Set once -q has seen a match, so no more files are searched.
+    let stop = AtomicBool::new(false);
+    // Files are numbered in the order they're found, so the writer can put
+    // their results back in that order.
+    let (work_sender, work) = mpsc::sync_channel::<(usize, String)>(threads * QUEUE_PER_THREAD);
+    let work = Mutex::new(work);
+    let (result_sender, results) = mpsc::channel::<(usize, Stats, Capture)>();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (stop, work, result_sender) = (&stop, &work, result_sender.clone());
+            let matcher = matcher.clone();
+            scope.spawn(move || {
+                IN_WORKER.with(|in_worker| in_worker.set(true));
+                loop {
+                    let next = work.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    let Ok((index, filename)) = next else {
+                        break;
+                    };
+                    // A skipped file still gets an empty result, so the writer
+                    // isn't left waiting for it.
+                    let (stats, captured) = if stop.load(Ordering::Relaxed) {
+                        Default::default()
+                    } else {
+                        output::capture(|| search_and_report(options, &matcher, &filename))
+                    };
+                    if options.quiet && stats.matched_lines > 0 {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    if result_sender.send((index, stats, captured)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+        let writer = scope.spawn(|| {
+            let mut total = Stats::default();
+            // Results that came in before those of files found earlier.
+            let mut waiting = BTreeMap::new();
+            let mut next_index = 0;
+            for (index, stats, captured) in results {
+                total.add(&stats);
+                if options.no_sort {
+                    output::write_captured(captured);
+                    continue;
+                }
+                waiting.insert(index, captured);
+                while let Some(captured) = waiting.remove(&next_index) {
+                    output::write_captured(captured);
+                    next_index += 1;
+                }
+            }
+            total
+        });
+
+        let mut found = 0;
+        for_each_file(options, &mut |filename| {
+            let index = found;
+            found += 1;
+            !stop.load(Ordering::Relaxed) && work_sender.send((index, filename.to_string())).is_ok()
+        });
+        drop(work_sender);
+        writer.join().unwrap_or_default()
+    })
+}
@@ -0,0 +1,332 @@
+use std::fs;
+
+use crate::inflate;
+
+// Text extraction for PDF files. Every uncompressed or Flate-compressed
+// stream is scanned for text drawing operators between BT and ET, and the
+// strings they draw are kept, with a line break wherever the text moves to a
+// new line. Strings are read as one byte per character, which covers the
+// simple fonts most generated documents use; text in composite (CID) fonts
+// comes out as whatever its glyph codes happen to spell.
+
+pub fn is_document(filename: &str) -> bool {
+    filename.to_ascii_lowercase().ends_with(".pdf")
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack.get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8], before: usize) -> Option<usize> {
+    haystack[..before].windows(needle.len()).rposition(|window| window == needle)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    find(haystack, needle, 0).is_some()
+}
+
+// Flate streams are zlib data: a two byte header, then deflate.
+fn zlib_inflate(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0f != 8 {
+        return None;
+    }
+    inflate::inflate(&data[2..], usize::MAX).ok()
+}
+
+// The decoded contents of every stream that could hold page content; images,
+// fonts and cross-reference streams are skipped.
+fn streams(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut streams = Vec::new();
+    let mut at = 0;
+    while let Some(keyword) = find(data, b"stream", at) {
+        at = keyword + b"stream".len();
+        if keyword >= 3 && &data[keyword - 3..keyword] == b"end" {
+            continue;
+        }
+        let mut start = at;
+        if data.get(start) == Some(&b'\r') {
+            start += 1;
+        }
+        if data.get(start) != Some(&b'\n') {
+            continue;
+        }
+        start += 1;
+        let end = match find(data, b"endstream", start) {
+            Some(end) => end,
+            None => break,
+        };
+        at = end + b"endstream".len();
+        let dictionary = &data[rfind(data, b"obj", keyword).unwrap_or(0)..keyword];
+        if contains(dictionary, b"/Image") || contains(dictionary, b"/XRef") || contains(dictionary, b"/FontFile") {
+            continue;
+        }
+        let raw = &data[start..end];
+        if contains(dictionary, b"/FlateDecode") {
+            if let Some(decoded) = zlib_inflate(raw) {
+                streams.push(decoded);
+            }
+        } else if !contains(dictionary, b"/Filter") {
+            streams.push(raw.to_vec());
+        }
+    }
+    streams
+}
+
+enum Operand {
+    Number(f64),
+    Text(Vec<u8>),
+    Array(Vec<Operand>),
+    Other,
+}
+
+struct Lexer<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.at).copied()
+    }
+
+    fn literal_string(&mut self) -> Vec<u8> {
+        let mut text = Vec::new();
+        let mut depth = 1;
+        while let Some(byte) = self.peek() {
+            self.at += 1;
+            match byte {
+                b'(' => depth += 1,
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                b'\\' => {
+                    let Some(escaped) = self.peek() else {
+                        break;
+                    };
+                    self.at += 1;
+                    match escaped {
+                        b'n' => text.push(b'\n'),
+                        b'r' => text.push(b'\r'),
+                        b't' => text.push(b'\t'),
+                        b'b' | b'f' => {}
+                        b'0'..=b'7' => {
+                            let mut value = (escaped - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(digit @ b'0'..=b'7') => {
+                                        value = value * 8 + (digit - b'0') as u32;
+                                        self.at += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            text.push(value as u8);
+                        }
+                        b'\n' => {}
+                        b'\r' => {
+                            if self.peek() == Some(b'\n') {
+                                self.at += 1;
+                            }
+                        }
+                        other => text.push(other),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            text.push(byte);
+        }
+        text
+    }
+
+    fn hex_string(&mut self) -> Vec<u8> {
+        let mut digits = Vec::new();
+        while let Some(byte) = self.peek() {
+            self.at += 1;
+            if byte == b'>' {
+                break;
+            }
+            if let Some(digit) = (byte as char).to_digit(16) {
+                digits.push(digit as u8);
+            }
+        }
+        if digits.len() % 2 == 1 {
+            digits.push(0);
+        }
+        digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect()
+    }
+
+    fn word(&mut self) -> &'a [u8] {
+        let start = self.at;
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_whitespace() || b"()<>[]{}/%".contains(&byte) {
+                break;
+            }
+            self.at += 1;
+        }
+        &self.data[start..self.at]
+    }
+}
+
+enum Item<'a> {
+    Operand(Operand),
+    ArrayStart,
+    ArrayEnd,
+    Operator(&'a [u8]),
+}
+
+fn next_item<'a>(lexer: &mut Lexer<'a>) -> Option<Item<'a>> {
+    loop {
+        let byte = lexer.peek()?;
+        match byte {
+            b if b.is_ascii_whitespace() => lexer.at += 1,
+            b'%' => {
+                while !matches!(lexer.peek(), None | Some(b'\n') | Some(b'\r')) {
+                    lexer.at += 1;
+                }
+            }
+            b'(' => {
+                lexer.at += 1;
+                return Some(Item::Operand(Operand::Text(lexer.literal_string())));
+            }
+            b'<' if lexer.data.get(lexer.at + 1) == Some(&b'<') => {
+                lexer.at += 2;
+                return Some(Item::Operand(Operand::Other));
+            }
+            b'>' if lexer.data.get(lexer.at + 1) == Some(&b'>') => {
+                lexer.at += 2;
+                return Some(Item::Operand(Operand::Other));
+            }
+            b'<' => {
+                lexer.at += 1;
+                return Some(Item::Operand(Operand::Text(lexer.hex_string())));
+            }
+            b'[' => {
+                lexer.at += 1;
+                return Some(Item::ArrayStart);
+            }
+            b']' => {
+                lexer.at += 1;
+                return Some(Item::ArrayEnd);
+            }
+            b'/' => {
+                lexer.at += 1;
+                lexer.word();
+                return Some(Item::Operand(Operand::Other));
+            }
+            _ => {
+                let word = lexer.word();
+                if word.is_empty() {
+                    lexer.at += 1;
+                    continue;
+                }
+                let number = std::str::from_utf8(word).ok().and_then(|word| word.parse::<f64>().ok());
+                return Some(match number {
+                    Some(number) => Item::Operand(Operand::Number(number)),
+                    None => Item::Operator(word),
+                });
+            }
+        }
+    }
+}
+
+fn new_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn push_text(bytes: &[u8], out: &mut String) {
+    out.extend(bytes.iter().filter(|&&b| b != 0).map(|&b| b as char));
+}
+
+// In a TJ array, numbers adjust the spacing between strings; a large enough
+// gap is taken to be a space between words.
+const WORD_GAP: f64 = -200.0;
+
+fn content_text(content: &[u8], out: &mut String) {
+    let mut lexer = Lexer { data: content, at: 0 };
+    let mut operands: Vec<Operand> = Vec::new();
+    let mut arrays: Vec<Vec<Operand>> = Vec::new();
+    let mut in_text = false;
+    let mut last_y = None;
+    while let Some(item) = next_item(&mut lexer) {
+        let operand = match item {
+            Item::Operand(operand) => operand,
+            Item::ArrayStart => {
+                arrays.push(Vec::new());
+                continue;
+            }
+            Item::ArrayEnd => Operand::Array(arrays.pop().unwrap_or_default()),
+            Item::Operator(operator) => {
+                let number = |i: usize| match operands.get(i) {
+                    Some(Operand::Number(n)) => *n,
+                    _ => 0.0,
+                };
+                match operator {
+                    b"BT" => in_text = true,
+                    b"ET" => {
+                        in_text = false;
+                        new_line(out);
+                    }
+                    b"T*" if in_text => new_line(out),
+                    b"Td" | b"TD" if in_text && number(1) != 0.0 => new_line(out),
+                    b"Tm" if in_text => {
+                        let y = number(5);
+                        if last_y.is_some_and(|last| last != y) {
+                            new_line(out);
+                        }
+                        last_y = Some(y);
+                    }
+                    b"Tj" | b"'" | b"\"" if in_text => {
+                        if operator != b"Tj" {
+                            new_line(out);
+                        }
+                        if let Some(Operand::Text(text)) = operands.last() {
+                            push_text(text, out);
+                        }
+                    }
+                    b"TJ" if in_text => {
+                        if let Some(Operand::Array(items)) = operands.last() {
+                            for item in items {
+                                match item {
+                                    Operand::Text(text) => push_text(text, out),
+                                    Operand::Number(gap) if *gap < WORD_GAP => out.push(' '),
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                operands.clear();
+                continue;
+            }
+        };
+        match arrays.last_mut() {
+            Some(array) => array.push(operand),
+            None => operands.push(operand),
+        }
+    }
+}
+
+pub fn text(filename: &str) -> Result<String, String> {
+    let data = fs::read(filename).map_err(|e| e.to_string())?;
+    if !data.starts_with(b"%PDF") {
+        return Err("not a PDF file".to_string());
+    }
+    let mut out = String::new();
+    for stream in streams(&data) {
+        if contains(&stream, b"BT") {
+            content_text(&stream, &mut out);
+        }
+    }
+    new_line(&mut out);
+    Ok(out)
+}
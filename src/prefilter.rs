@@ -0,0 +1,64 @@
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex_syntax::hir::literal::{ExtractKind, Extractor, Seq};
+use regex_syntax::hir::Hir;
+
+// Finds lines that can't match before the regex sees them. Every match of
+// the patterns either starts with one of a few literal strings or ends with
+// one, so a line holding none of them is skipped without being matched, and
+// a run of such lines is skipped with one scan of the read buffer.
+#[derive(Clone)]
+pub struct Prefilter {
+    literals: AhoCorasick,
+}
+
+impl Prefilter {
+    // Returns None when some pattern has no such literals, or only ones too
+    // short or too many to be worth looking for. The patterns are parsed with
+    // the same flags the regexes are built with.
+    pub fn new(patterns: &[String], parse: impl Fn(&str) -> Option<Hir>) -> Option<Prefilter> {
+        let mut literals = Vec::new();
+        for pattern in patterns {
+            let hir = parse(pattern)?;
+            let seq = required_literals(&hir, ExtractKind::Prefix).or_else(|| required_literals(&hir, ExtractKind::Suffix))?;
+            literals.extend(seq.literals()?.iter().map(|literal| literal.as_bytes().to_vec()));
+        }
+        if literals.is_empty() {
+            return None;
+        }
+        let literals = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(&literals)
+            .ok()?;
+        Some(Prefilter { literals })
+    }
+
+    // How many bytes at the start of `buf` are whole lines that can't match.
+    pub fn skippable(&self, buf: &[u8], terminator: u8) -> usize {
+        let end = match self.literals.find(buf) {
+            Some(found) => found.start(),
+            None => buf.len(),
+        };
+        memchr::memrchr(terminator, &buf[..end]).map_or(0, |i| i + 1)
+    }
+}
+
+// Lines are matched as UTF-8 with invalid bytes read as U+FFFD, so a literal
+// must be valid UTF-8 without U+FFFD for the raw bytes of every line it's in
+// to hold it too. Single bytes are left to the regex, as they're likely
+// found on most lines anyway.
+fn required_literals(hir: &Hir, kind: ExtractKind) -> Option<Seq> {
+    let mut seq = Extractor::new().kind(kind.clone()).extract(hir);
+    match kind {
+        ExtractKind::Prefix => seq.optimize_for_prefix_by_preference(),
+        _ => seq.optimize_for_suffix_by_preference(),
+    }
+    let usable = seq.literals()?.iter().all(|literal| {
+        literal.as_bytes().len() > 1
+            && std::str::from_utf8(literal.as_bytes()).is_ok_and(|text| !text.contains('\u{fffd}'))
+    });
+    if usable {
+        Some(seq)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+// Reads the standard output of a command. A command that fails is
+// reported as a read error once its output runs out, with what it printed
+// to stderr; the error is kept so every later read reports it too.
+struct CommandReader {
+    program: String,
+    child: Child,
+    stdout: ChildStdout,
+    failure: Option<String>,
+}
+
+impl CommandReader {
+    fn check_exit(&mut self) -> io::Result<()> {
+        if self.failure.is_none() {
+            let status = self.child.wait()?;
+            if status.success() {
+                return Ok(());
+            }
+            let mut message = String::new();
+            if let Some(stderr) = self.child.stderr.as_mut() {
+                let _ = stderr.read_to_string(&mut message);
+            }
+            let message = message.trim();
+            self.failure = Some(if message.is_empty() {
+                format!("{} failed: {}", self.program, status)
+            } else {
+                message.to_string()
+            });
+        }
+        Err(io::Error::other(self.failure.clone().unwrap_or_default()))
+    }
+}
+
+impl Read for CommandReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.check_exit()?;
+        }
+        Ok(n)
+    }
+}
+
+// A search can stop before the end of the output, e.g. with -q or -l, so the
+// command may still be running.
+impl Drop for CommandReader {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+// Starts `command` with its output piped, for reading as an input to search.
+// `program` names it in error messages.
+pub fn spawn_reader(mut command: Command, program: &str) -> io::Result<Box<dyn BufRead>> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't run {}: {}", program, e)))?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    Ok(Box::new(BufReader::new(CommandReader {
+        program: program.to_string(),
+        child,
+        stdout,
+        failure: None,
+    })))
+}
@@ -0,0 +1,124 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::output;
+
+// For --progress: a line on standard error with what's been searched so far,
+// redrawn in place. It's only shown once the search has run for a while, so
+// quick searches never show it, and it's erased when the search ends.
+
+const DELAY: Duration = Duration::from_secs(1);
+const INTERVAL: Duration = Duration::from_millis(200);
+
+// A search adds to the counts after about this many bytes, and at the end.
+pub const STEP: usize = 1 << 20;
+
+static FILES: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static MATCHED_LINES: AtomicUsize = AtomicUsize::new(0);
+
+// Whether the line is on the screen, and whether output to standard output
+// goes to the same terminal, in which case it has to be erased first.
+static SHOWN: AtomicBool = AtomicBool::new(false);
+static SHARES_TERMINAL: AtomicBool = AtomicBool::new(false);
+
+static DONE: AtomicBool = AtomicBool::new(false);
+static DRAWER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+pub fn add_file() {
+    FILES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn add(bytes: usize, matched_lines: usize) {
+    BYTES.fetch_add(bytes, Ordering::Relaxed);
+    MATCHED_LINES.fetch_add(matched_lines, Ordering::Relaxed);
+}
+
+// Starts drawing the line once DELAY has passed, unless standard error isn't a
+// terminal. When standard output is the same terminal the line is drawn only
+// between whole lines of output, which a block-buffered standard output
+// can't promise.
+pub fn start(line_buffered: bool) {
+    if !io::stderr().is_terminal() {
+        return;
+    }
+    let shares_terminal = io::stdout().is_terminal();
+    if shares_terminal && !line_buffered {
+        return;
+    }
+    SHARES_TERMINAL.store(shares_terminal, Ordering::Relaxed);
+    let started = Instant::now();
+    let drawer = thread::spawn(move || {
+        thread::park_timeout(DELAY);
+        while !DONE.load(Ordering::Relaxed) {
+            draw(started);
+            thread::park_timeout(INTERVAL);
+        }
+    });
+    *DRAWER.lock().unwrap_or_else(|e| e.into_inner()) = Some(drawer);
+}
+
+pub fn stop() {
+    DONE.store(true, Ordering::Relaxed);
+    if let Some(drawer) = DRAWER.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        drawer.thread().unpark();
+        let _ = drawer.join();
+    }
+    clear_for_message();
+}
+
+// Erases the line before output to the terminal it's on. Called with
+// standard output locked, so it isn't redrawn in between.
+pub fn clear_for_output() {
+    if SHARES_TERMINAL.load(Ordering::Relaxed) {
+        clear_for_message();
+    }
+}
+
+// Erases the line before a message on standard error, which would otherwise
+// run into it.
+pub fn clear_for_message() {
+    if SHOWN.swap(false, Ordering::Relaxed) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+fn draw(started: Instant) {
+    let _stdout = io::stdout().lock();
+    if SHARES_TERMINAL.load(Ordering::Relaxed) && !output::at_line_start() {
+        return;
+    }
+    let files = FILES.load(Ordering::Relaxed);
+    let matched_lines = MATCHED_LINES.load(Ordering::Relaxed);
+    let line = format!(
+        "\r\x1b[K{} file{}, {} searched, {} matching line{} ({}s)",
+        files,
+        if files == 1 { "" } else { "s" },
+        human_size(BYTES.load(Ordering::Relaxed)),
+        matched_lines,
+        if matched_lines == 1 { "" } else { "s" },
+        started.elapsed().as_secs(),
+    );
+    let mut stderr = io::stderr().lock();
+    let _ = stderr.write_all(line.as_bytes());
+    let _ = stderr.flush();
+    SHOWN.store(true, Ordering::Relaxed);
+}
+
+// Sizes in the units --max-filesize and the like take.
+fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
@@ -0,0 +1,382 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::matcher::Matcher;
+use crate::output;
+use crate::stats::Stats;
+use crate::{is_selected, match_line, max_count_reached, trim_line_ending, warn, MinigrepOptions};
+
+// One line of a file, with its terminator, and the text that replaces it if
+// the substitution changed it. With -U it's all the lines a match spans.
+struct Edit<'a> {
+    original: &'a str,
+    replaced: Option<String>,
+}
+
+// Applies the --replace template to every selected line of `content`. Line
+// terminators are kept as they were.
+fn replace_lines<'a>(options: &MinigrepOptions, matcher: &Matcher, template: &str, content: &'a str) -> Vec<Edit<'a>> {
+    let mut edits = Vec::new();
+    let mut selected_lines = 0;
+    let terminator = if options.null_data { '\0' } else { '\n' };
+    for raw in content.split_inclusive(terminator) {
+        let mut text = raw.to_string();
+        let ending = trim_line_ending(options, &mut text);
+        let selected = is_selected(options, match_line(options, matcher, &text))
+            && !max_count_reached(options, selected_lines);
+        let mut edit = Edit {
+            original: raw,
+            replaced: None,
+        };
+        if !selected {
+            edits.push(edit);
+            continue;
+        }
+        selected_lines += 1;
+        let mut new_text = String::with_capacity(text.len());
+        let mut last_end = 0;
+        for (m, replacement) in matcher.replacements(&text, template) {
+            new_text.push_str(&text[last_end..m.start()]);
+            new_text.push_str(&replacement);
+            last_end = m.end();
+        }
+        new_text.push_str(&text[last_end..]);
+        if new_text != text {
+            new_text.push_str(ending);
+            edit.replaced = Some(new_text);
+        }
+        edits.push(edit);
+    }
+    edits
+}
+
+// Applies the --replace template over the whole of `content` for -U, so
+// matches can span lines. The lines touched by a match, or by matches that
+// share a line, become a single edit.
+fn replace_multiline<'a>(options: &MinigrepOptions, matcher: &Matcher, template: &str, content: &'a str) -> Vec<Edit<'a>> {
+    let terminator = if options.null_data { '\0' } else { '\n' };
+    let mut starts = vec![0];
+    starts.extend(content.match_indices(terminator).map(|(i, _)| i + 1).filter(|&start| start < content.len()));
+    let line_of = |pos: usize| starts.partition_point(|&start| start <= pos) - 1;
+    let line_end = |line: usize| starts.get(line + 1).copied().unwrap_or(content.len());
+
+    // Each group is its first and last line and the matches in it.
+    let mut groups: Vec<(usize, usize, Vec<_>)> = Vec::new();
+    if !options.invert_match {
+        let mut selected_lines = 0;
+        for (m, replacement) in matcher.replacements(content, template) {
+            let first = line_of(m.start());
+            let last = line_of(m.end().saturating_sub(1).max(m.start()));
+            match groups.last_mut() {
+                Some(group) if first <= group.1 => {
+                    selected_lines += last.saturating_sub(group.1);
+                    group.1 = group.1.max(last);
+                    group.2.push((m, replacement));
+                }
+                _ => {
+                    if max_count_reached(options, selected_lines) {
+                        break;
+                    }
+                    selected_lines += last - first + 1;
+                    groups.push((first, last, vec![(m, replacement)]));
+                }
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let mut line = 0;
+    for (first, last, replacements) in groups {
+        for before in line..first {
+            edits.push(Edit {
+                original: &content[starts[before]..line_end(before)],
+                replaced: None,
+            });
+        }
+        let (start, end) = (starts[first], line_end(last));
+        let mut new_text = String::with_capacity(end - start);
+        let mut last_end = start;
+        for (m, replacement) in replacements {
+            new_text.push_str(&content[last_end..m.start()]);
+            new_text.push_str(&replacement);
+            last_end = m.end();
+        }
+        new_text.push_str(&content[last_end..end]);
+        let original = &content[start..end];
+        edits.push(Edit {
+            original,
+            replaced: Some(new_text).filter(|new_text| new_text != original),
+        });
+        line = last + 1;
+    }
+    for after in line..starts.len() {
+        if starts[after] < content.len() {
+            edits.push(Edit {
+                original: &content[starts[after]..line_end(after)],
+                replaced: None,
+            });
+        }
+    }
+    edits
+}
+
+// How many lines of the original file an edit covers.
+fn line_count(edit: &Edit) -> usize {
+    edit.original.split_inclusive('\n').count()
+}
+
+const DIFF_CONTEXT: usize = 3;
+
+fn push_diff_line(diff: &mut String, marker: char, line: &str) {
+    diff.push(marker);
+    diff.push_str(line);
+    if !line.ends_with('\n') {
+        diff.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+// Formats the edits as a unified diff with three lines of context, the way
+// `diff -u` shows them.
+fn unified_diff(filename: &str, edits: &[Edit]) -> String {
+    let changed: Vec<usize> = edits.iter()
+        .enumerate()
+        .filter(|(_, edit)| edit.replaced.is_some())
+        .map(|(i, _)| i)
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + 1 + DIFF_CONTEXT).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", filename, filename);
+    // Replacements may contain newlines, so later hunks can start at a
+    // different line number in the new file.
+    let mut shift: isize = 0;
+    for (start, end) in hunks {
+        let old_start = edits[..start].iter().map(line_count).sum::<usize>();
+        let old_len = edits[start..end].iter().map(line_count).sum::<usize>();
+        let (hunk, new_len) = format_hunk(&edits[start..end], old_start, (old_start as isize + shift) as usize);
+        diff.push_str(&hunk);
+        shift += new_len as isize - old_len as isize;
+    }
+    diff
+}
+
+// Formats one hunk whose first line is line `old_start` (counting from 0) of
+// the original and `new_start` of the result. Also returns the hunk's length
+// in the new file.
+fn format_hunk(edits: &[Edit], old_start: usize, new_start: usize) -> (String, usize) {
+    let mut body = String::new();
+    let mut new_len = 0;
+    for edit in edits {
+        match &edit.replaced {
+            Some(replaced) => {
+                for line in edit.original.split_inclusive('\n') {
+                    push_diff_line(&mut body, '-', line);
+                }
+                for line in replaced.split_inclusive('\n') {
+                    push_diff_line(&mut body, '+', line);
+                    new_len += 1;
+                }
+            }
+            None => {
+                push_diff_line(&mut body, ' ', edit.original);
+                new_len += 1;
+            }
+        }
+    }
+    let old_len = edits.iter().map(line_count).sum::<usize>();
+    let header = format!("@@ -{},{} +{},{} @@\n", old_start + 1, old_len, new_start + 1, new_len);
+    (header + &body, new_len)
+}
+
+const ASK: u8 = 0;
+const ACCEPT_ALL: u8 = 1;
+const QUIT: u8 = 2;
+
+// Whether --interactive still asks about each change, or the user answered
+// "a" or "q" for everything that's left in this and later files.
+static INTERACTIVE_STATE: AtomicU8 = AtomicU8::new(ASK);
+
+// Answers are read from the terminal rather than standard input, which may be
+// one of the files being searched.
+fn read_answer() -> Option<String> {
+    let mut answer = String::new();
+    let read = match File::open("/dev/tty") {
+        Ok(tty) => BufReader::new(tty).read_line(&mut answer),
+        Err(_) => io::stdin().lock().read_line(&mut answer),
+    };
+    match read {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
+// Shows each change with its context on stderr and asks whether to apply it,
+// like `git add -p`. Rejected changes are dropped from `edits`.
+fn confirm_edits(filename: &str, edits: &mut [Edit]) {
+    for i in 0..edits.len() {
+        if edits[i].replaced.is_none() {
+            continue;
+        }
+        match INTERACTIVE_STATE.load(Ordering::Relaxed) {
+            ACCEPT_ALL => return,
+            QUIT => {
+                edits[i].replaced = None;
+                continue;
+            }
+            _ => {}
+        }
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + 1 + DIFF_CONTEXT).min(edits.len());
+        let view: Vec<Edit> = edits[start..end].iter()
+            .enumerate()
+            .map(|(j, edit)| Edit {
+                original: edit.original,
+                replaced: if start + j == i { edit.replaced.clone() } else { None },
+            })
+            .collect();
+        let line = edits[..start].iter().map(line_count).sum();
+        let (hunk, _) = format_hunk(&view, line, line);
+        eprint!("--- {}\n{}", filename, hunk);
+        loop {
+            eprint!("Apply this change [y,n,a,q,?]? ");
+            let answer = read_answer();
+            match answer.as_deref() {
+                Some("y") => {}
+                Some("n") => edits[i].replaced = None,
+                Some("a") => INTERACTIVE_STATE.store(ACCEPT_ALL, Ordering::Relaxed),
+                Some("q") | None => {
+                    INTERACTIVE_STATE.store(QUIT, Ordering::Relaxed);
+                    edits[i].replaced = None;
+                }
+                _ => {
+                    eprintln!("y - apply this change");
+                    eprintln!("n - skip this change");
+                    eprintln!("a - apply this and all remaining changes");
+                    eprintln!("q - skip this and all remaining changes");
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+}
+
+// Rewrites a file in place with the replacements applied, or with --diff only
+// prints the changes that would be made. The new content goes to a temporary
+// file in the same directory, which is renamed over the original, so readers
+// never see a half-written file.
+pub fn rewrite_file(options: &MinigrepOptions, matcher: &Matcher, template: &str, filename: &str) -> Stats {
+    if filename == "-" {
+        warn(options, "can't rewrite standard input in place");
+        return Stats::default();
+    }
+    let content = match fs::read_to_string(filename) {
+        Ok(content) => content,
+        Err(e) => {
+            warn(options, &format!("couldn't read {}: {}", filename, e));
+            return Stats::default();
+        }
+    };
+    // A UTF-8 byte order mark is kept out of the matching and written back
+    // as it was.
+    let (bom, content) = match content.strip_prefix('\u{feff}') {
+        Some(rest) => ("\u{feff}", rest),
+        None => ("", content.as_str()),
+    };
+    let mut edits = if options.multiline {
+        replace_multiline(options, matcher, template, content)
+    } else {
+        replace_lines(options, matcher, template, content)
+    };
+    if options.interactive && !options.diff {
+        confirm_edits(filename, &mut edits);
+    }
+    let changed_lines = edits.iter().filter(|edit| edit.replaced.is_some()).count();
+    let file_stats = Stats {
+        searches: 1,
+        searches_with_match: (changed_lines > 0) as usize,
+        bytes_searched: content.len(),
+        matched_lines: changed_lines,
+        ..Default::default()
+    };
+    if changed_lines == 0 {
+        return file_stats;
+    }
+    if options.diff {
+        output::print(format_args!("{}", unified_diff(filename, &edits)));
+        return file_stats;
+    }
+    let replaced: String = std::iter::once(bom)
+        .chain(edits.iter().map(|edit| edit.replaced.as_deref().unwrap_or(edit.original)))
+        .collect();
+    if let Some(suffix) = &options.backup_suffix {
+        let backup = format!("{}{}", filename, suffix);
+        if let Err(e) = fs::copy(filename, &backup) {
+            warn(options, &format!("couldn't back up {} to {}: {}", filename, backup, e));
+            return file_stats;
+        }
+    }
+    if let Err(e) = replace_contents(Path::new(filename), &replaced) {
+        warn(options, &format!("couldn't write {}: {}", filename, e));
+    }
+    file_stats
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.minigrep-{}", name, process::id()))
+}
+
+fn replace_contents(path: &Path, contents: &str) -> std::io::Result<()> {
+    let permissions = fs::metadata(path)?.permissions();
+    let temp = temp_path(path);
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::set_permissions(&temp, permissions)?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replace_multiline, unified_diff};
+    use crate::matcher::{Matcher, MatcherConfig};
+    use crate::MinigrepOptions;
+
+    #[test]
+    fn multiline_replacements_span_lines() {
+        let options = MinigrepOptions {
+            multiline: true,
+            ..MinigrepOptions::default()
+        };
+        let config = MatcherConfig {
+            multi_line: true,
+            ..MatcherConfig::default()
+        };
+        let matcher = Matcher::new(&[r"foo\nbar".to_string()], &config).unwrap();
+        let content = "one\nfoo\nbar baz\ntwo\n";
+        let edits = replace_multiline(&options, &matcher, "X", content);
+        let rewritten: String = edits.iter().map(|edit| edit.replaced.as_deref().unwrap_or(edit.original)).collect();
+        assert_eq!(rewritten, "one\nX baz\ntwo\n");
+        assert_eq!(
+            unified_diff("f", &edits),
+            "--- a/f\n+++ b/f\n@@ -1,4 +1,3 @@\n one\n-foo\n-bar baz\n+X baz\n two\n",
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+// Counters for one file, or summed over the whole run.
+#[derive(Clone, Default)]
+pub struct Stats {
+    pub elapsed: Duration,
+    pub searches: usize,
+    pub searches_with_match: usize,
+    pub bytes_searched: usize,
+    pub bytes_printed: usize,
+    pub matched_lines: usize,
+    pub matches: usize,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.elapsed += other.elapsed;
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.bytes_searched += other.bytes_searched;
+        self.bytes_printed += other.bytes_printed;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+
+    // The --stats summary, laid out like rg's.
+    pub fn summary(&self, elapsed_total: Duration) -> String {
+        format!(
+            "{} matches\n{} matched lines\n{} files contained matches\n{} files searched\n\
+             {} bytes searched\n{:.6} seconds spent searching\n{:.6} seconds\n",
+            self.matches,
+            self.matched_lines,
+            self.searches_with_match,
+            self.searches,
+            self.bytes_searched,
+            self.elapsed.as_secs_f64(),
+            elapsed_total.as_secs_f64(),
+        )
+    }
+}
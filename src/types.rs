@@ -0,0 +1,96 @@
+use crate::glob::Glob;
+
+// The file types --type and --type-not accept, each with the globs that
+// select its files.
+const BUILTIN_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("cmake", &["CMakeLists.txt", "*.cmake"]),
+    ("cpp", &["*.cpp", "*.cc", "*.cxx", "*.c++", "*.hpp", "*.hh", "*.hxx", "*.h++", "*.h", "*.inl"]),
+    ("cs", &["*.cs"]),
+    ("css", &["*.css", "*.scss", "*.sass", "*.less"]),
+    ("docker", &["Dockerfile", "*.dockerfile", "Dockerfile.*"]),
+    ("go", &["*.go"]),
+    ("html", &["*.html", "*.htm", "*.xhtml"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs", "*.cjs", "*.jsx"]),
+    ("json", &["*.json", "*.jsonl"]),
+    ("kotlin", &["*.kt", "*.kts"]),
+    ("lua", &["*.lua"]),
+    ("make", &["Makefile", "makefile", "GNUmakefile", "*.mk", "*.mak"]),
+    ("markdown", &["*.md", "*.markdown", "*.mdx"]),
+    ("php", &["*.php"]),
+    ("py", &["*.py", "*.pyi", "*.pyw"]),
+    ("rb", &["*.rb", "*.rake", "*.gemspec", "Gemfile", "Rakefile"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh", ".bashrc", ".zshrc", ".profile"]),
+    ("sql", &["*.sql"]),
+    ("swift", &["*.swift"]),
+    ("toml", &["*.toml", "Cargo.lock"]),
+    ("ts", &["*.ts", "*.tsx", "*.mts", "*.cts"]),
+    ("txt", &["*.txt"]),
+    ("xml", &["*.xml", "*.xsd", "*.xsl", "*.xslt", "*.svg"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+// The built-in types along with those defined by --type-add.
+#[derive(Default)]
+pub struct FileTypes {
+    added: Vec<(String, Vec<String>)>,
+}
+
+impl FileTypes {
+    // Adds the type defined by "NAME:GLOB,GLOB...". Naming a type that
+    // already exists adds the globs to it.
+    pub fn add(&mut self, definition: &str) -> Result<(), String> {
+        let invalid = || format!("invalid file type definition {:?}: expected NAME:GLOB[,GLOB...]", definition);
+        let (name, patterns) = definition.split_once(':').ok_or_else(invalid)?;
+        let patterns: Vec<String> = patterns.split(',')
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+        if name.is_empty() || patterns.is_empty() {
+            return Err(invalid());
+        }
+        for pattern in &patterns {
+            Glob::new(pattern)?;
+        }
+        match self.added.iter_mut().find(|(added_name, _)| added_name == name) {
+            Some((_, added_patterns)) => added_patterns.extend(patterns),
+            None => self.added.push((name.to_string(), patterns)),
+        }
+        Ok(())
+    }
+
+    fn patterns(&self, name: &str) -> Vec<&str> {
+        let builtin = BUILTIN_TYPES.iter()
+            .filter(|(type_name, _)| *type_name == name)
+            .flat_map(|(_, patterns)| patterns.iter().copied());
+        let added = self.added.iter()
+            .filter(|(type_name, _)| type_name == name)
+            .flat_map(|(_, patterns)| patterns.iter().map(String::as_str));
+        builtin.chain(added).collect()
+    }
+
+    // One "NAME: GLOB, GLOB..." line for each type, sorted by name, for
+    // --type-list.
+    pub fn list(&self) -> String {
+        let mut names: Vec<&str> = BUILTIN_TYPES.iter()
+            .map(|(name, _)| *name)
+            .chain(self.added.iter().map(|(name, _)| name.as_str()))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names.iter()
+            .map(|name| format!("{}: {}\n", name, self.patterns(name).join(", ")))
+            .collect()
+    }
+
+    // The globs of the type called `name`.
+    pub fn globs(&self, name: &str) -> Result<Vec<Glob>, String> {
+        let patterns = self.patterns(name);
+        if patterns.is_empty() {
+            return Err(format!("unknown file type: {} (--type-list shows them all)", name));
+        }
+        patterns.into_iter().map(Glob::new).collect()
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::glob::Glob;
+use crate::ignore::{self, IgnoreRules};
+use crate::{git, warn, MinigrepOptions};
+
+// Whether the filters on file names let a file be searched: with --include
+// or --type, its name has to match one of their globs, and it mustn't match
+// any --exclude or --type-not glob. Only the name is looked at, so excluded
+// files are never opened.
+pub fn file_wanted(options: &MinigrepOptions, path: &Path) -> bool {
+    let matches = |globs: &[Glob]| globs.iter().any(|glob| glob.is_match(path));
+    (options.includes.is_empty() || matches(&options.includes))
+        && (options.types.is_empty() || matches(&options.types))
+        && !matches(&options.excludes)
+        && !matches(&options.types_not)
+}
+
+// Directories matching an --exclude-dir glob are skipped with everything in
+// them.
+pub fn dir_wanted(options: &MinigrepOptions, dir: &Path) -> bool {
+    !options.exclude_dirs.iter().any(|glob| glob.is_match(dir))
+}
+
+// Whether any directory between `root` and `path` is excluded, for files
+// that come from a list rather than the walk.
+pub fn in_excluded_dir(options: &MinigrepOptions, root: &Path, path: &Path) -> bool {
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| *dir != root && !dir.as_os_str().is_empty())
+        .any(|dir| !dir_wanted(options, dir))
+}
+
+// Files bigger than --max-filesize are skipped, with a note under --verbose.
+fn size_wanted(options: &MinigrepOptions, path: &Path, metadata: &fs::Metadata) -> bool {
+    let Some(max_filesize) = options.max_filesize else {
+        return true;
+    };
+    let wanted = metadata.len() <= max_filesize;
+    if !wanted && options.verbose {
+        eprintln!("note: skipping {}: its {} bytes are over --max-filesize", path.display(), metadata.len());
+    }
+    wanted
+}
+
+// The orders --sort can put files in: by path, last modification time or
+// size, each ascending.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Path,
+    Modified,
+    Size,
+}
+
+impl SortKey {
+    // "none" turns sorting back off, e.g. over a --sort in the config file.
+    pub fn parse(option: &str, value: &str) -> Result<Option<SortKey>, String> {
+        match value {
+            "path" => Ok(Some(SortKey::Path)),
+            "modified" => Ok(Some(SortKey::Modified)),
+            "size" => Ok(Some(SortKey::Size)),
+            "none" => Ok(None),
+            _ => Err(format!("invalid value for --{}: {} (expected path, modified, size or none)", option, value)),
+        }
+    }
+}
+
+// Files whose metadata can't be read, like standard input, sort first when
+// ordered by time or size. Ties are broken by path, so the order is the same
+// from run to run.
+pub fn sort_files(files: &mut [String], key: SortKey, reverse: bool) {
+    match key {
+        SortKey::Path => files.sort_by(|a, b| Path::new(a).cmp(Path::new(b))),
+        SortKey::Modified => files.sort_by_cached_key(|file| {
+            (fs::metadata(file).and_then(|metadata| metadata.modified()).ok(), PathBuf::from(file))
+        }),
+        SortKey::Size => files.sort_by_cached_key(|file| {
+            (fs::metadata(file).map(|metadata| metadata.len()).ok(), PathBuf::from(file))
+        }),
+    }
+    if reverse {
+        files.reverse();
+    }
+}
+
+// How many directories down from `root` `path` is; a file directly in
+// `root` is at depth 1.
+fn depth_below(root: &Path, path: &Path) -> usize {
+    path.strip_prefix(root).map_or(0, |relative| relative.components().count())
+}
+
+// Calls `visit` for every regular file under `dir` that `file_wanted` allows
+// and no ignore file excludes, or with --git-tracked every one that git
+// tracks when `dir` is in a repository. Returns false if `visit` asked to
+// stop.
+pub fn walk(options: &MinigrepOptions, dir: &Path, visit: &mut dyn FnMut(&Path) -> bool) -> bool {
+    if options.git_tracked {
+        match git::tracked_files(dir) {
+            // Files deleted from the work tree but not the index are skipped,
+            // and so are symlinks unless --follow is given, as in the walk
+            // below.
+            Ok(files) => return files.iter()
+                .filter(|file| {
+                    let metadata = if options.follow { fs::metadata(file) } else { fs::symlink_metadata(file) };
+                    metadata.is_ok_and(|metadata| metadata.is_file() && size_wanted(options, file, &metadata))
+                })
+                .filter(|file| file_wanted(options, file) && !in_excluded_dir(options, dir, file))
+                .filter(|file| options.max_depth.is_none_or(|max_depth| depth_below(dir, file) <= max_depth))
+                .all(|file| visit(file)),
+            Err(e) => {
+                if options.verbose {
+                    eprintln!("note: {}; searching every file under {}", e, dir.display());
+                }
+            }
+        }
+    }
+    // Ignore rules are matched against absolute paths, since the ones that
+    // apply from outside `dir` are relative to directories above it.
+    let absolute = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut walker = Walker {
+        options,
+        ignores: if options.no_ignore { Vec::new() } else { ignore::outer_rules(&absolute) },
+        open_dirs: Vec::new(),
+        device: None,
+        visit,
+    };
+    if let Ok(metadata) = fs::metadata(dir) {
+        if options.follow {
+            walker.open_dirs.extend(dir_id(dir, &metadata));
+        }
+        if options.one_file_system {
+            walker.device = device(&metadata);
+        }
+    }
+    walker.walk_dir(dir, &absolute, 1)
+}
+
+// What identifies a directory however it's reached, so --follow can tell
+// when a symlink leads back to a directory the walk is already inside.
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = std::path::PathBuf;
+
+#[cfg(unix)]
+fn dir_id(_path: &Path, metadata: &fs::Metadata) -> Option<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path, _metadata: &fs::Metadata) -> Option<DirId> {
+    fs::canonicalize(path).ok()
+}
+
+// The file system a file is on, for --one-file-system. Where that can't be
+// told, every file counts as being on the same one.
+#[cfg(unix)]
+fn device(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+// The state of a walk. `ignores` holds the rules of the ignore files in the
+// directory being read and above it, with those of each directory added on
+// the way in and dropped on the way out. With --follow, `open_dirs` likewise
+// holds the directories the walk is inside, and with --one-file-system
+// `device` is the file system it started on.
+struct Walker<'a> {
+    options: &'a MinigrepOptions,
+    ignores: Vec<IgnoreRules>,
+    open_dirs: Vec<DirId>,
+    device: Option<u64>,
+    visit: &'a mut dyn FnMut(&Path) -> bool,
+}
+
+impl Walker<'_> {
+    // Reads the entries of `dir`, which are `depth` levels below the
+    // directory the walk started from.
+    fn walk_dir(&mut self, dir: &Path, absolute: &Path, depth: usize) -> bool {
+        if self.options.max_depth.is_some_and(|max_depth| depth > max_depth) {
+            return true;
+        }
+        let outer = self.ignores.len();
+        if !self.options.no_ignore {
+            ignore::push_dir_rules(&mut self.ignores, absolute);
+        }
+        let finished = self.walk_entries(dir, absolute, depth);
+        self.ignores.truncate(outer);
+        finished
+    }
+
+    // Walks a subdirectory, unless it's on another file system with
+    // --one-file-system, or --follow is given and it's one the walk is
+    // already inside, reached again through a symlink. `metadata` is the
+    // directory's own if it was found through a symlink.
+    fn enter_dir(&mut self, dir: &Path, absolute: &Path, depth: usize, metadata: Option<fs::Metadata>) -> bool {
+        if !self.options.follow && self.device.is_none() {
+            return self.walk_dir(dir, absolute, depth);
+        }
+        let Ok(metadata) = metadata.map_or_else(|| fs::metadata(dir), Ok) else {
+            return self.walk_dir(dir, absolute, depth);
+        };
+        if self.device.is_some() && device(&metadata) != self.device {
+            return true;
+        }
+        let id = dir_id(dir, &metadata).filter(|_| self.options.follow);
+        let Some(id) = id else {
+            return self.walk_dir(dir, absolute, depth);
+        };
+        if self.open_dirs.contains(&id) {
+            warn(self.options, &format!("skipping {}: symlink loop", dir.display()));
+            return true;
+        }
+        self.open_dirs.push(id);
+        let finished = self.walk_dir(dir, absolute, depth);
+        self.open_dirs.pop();
+        finished
+    }
+
+    // Only looks up the file's size if there's a limit on it.
+    fn size_wanted(&self, entry: &fs::DirEntry, path: &Path, metadata: Option<fs::Metadata>) -> bool {
+        if self.options.max_filesize.is_none() {
+            return true;
+        }
+        match metadata.map_or_else(|| entry.metadata(), Ok) {
+            Ok(metadata) => size_wanted(self.options, path, &metadata),
+            Err(e) => {
+                warn(self.options, &format!("couldn't stat {}: {}", path.display(), e));
+                false
+            }
+        }
+    }
+
+    // Symlinks found during the walk are skipped, as with grep -r, unless
+    // --follow is given. Unreadable entries are reported and skipped.
+    fn walk_entries(&mut self, dir: &Path, absolute: &Path, depth: usize) -> bool {
+        let options = self.options;
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn(options, &format!("couldn't read directory {}: {}", dir.display(), e));
+                return true;
+            }
+        };
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn(options, &format!("couldn't read directory {}: {}", dir.display(), e));
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    warn(options, &format!("couldn't stat {}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            let (file_type, followed) = if file_type.is_symlink() && options.follow {
+                match fs::metadata(&path) {
+                    Ok(metadata) => (metadata.file_type(), Some(metadata)),
+                    Err(e) => {
+                        warn(options, &format!("couldn't follow {}: {}", path.display(), e));
+                        continue;
+                    }
+                }
+            } else {
+                (file_type, None)
+            };
+            // Hidden entries are skipped unless --hidden is given, though a
+            // hidden directory named on the command line is still searched.
+            if !options.hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let absolute_path = absolute.join(entry.file_name());
+            if ignore::is_ignored(&options.ignore_files, &self.ignores, &absolute_path, file_type.is_dir()) {
+                continue;
+            }
+            if file_type.is_dir() {
+                if dir_wanted(options, &path) && !self.enter_dir(&path, &absolute_path, depth + 1, followed) {
+                    return false;
+                }
+            } else if file_type.is_file()
+                && file_wanted(options, &path)
+                && self.size_wanted(&entry, &path, followed)
+                && !(self.visit)(&path)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}